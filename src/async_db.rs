@@ -0,0 +1,160 @@
+use crate::db::AbortReason;
+use crate::{Change, DbKey, EncodedChanges, GridDb, Version, WriteReport};
+
+use rkyv::{Archived, Deserialize, Infallible};
+use sled::transaction::TransactionError;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A handle to a [`GridDb`] for use from async code. Each operation runs the blocking sync version on tokio's blocking thread
+/// pool with [`tokio::task::spawn_blocking`], so sled I/O never stalls the async executor.
+///
+/// ## Ownership
+///
+/// Blocking tasks must own everything they touch, so they can't borrow the map. Instead, the map is moved into this handle
+/// and shared behind an `Arc<Mutex<_>>`. Cloning the handle is cheap, and every clone refers to the same map. Operations
+/// are serialized by the mutex, just like `&mut self` serializes them on [`GridDb`], and the mutex is only ever locked on a
+/// blocking thread.
+///
+/// Use [`AsyncGridDb::run`] for any operation that doesn't have its own method.
+pub struct AsyncGridDb<K> {
+    map: Arc<Mutex<GridDb<K>>>,
+}
+
+impl<K> Clone for AsyncGridDb<K> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<K> From<GridDb<K>> for AsyncGridDb<K> {
+    fn from(map: GridDb<K>) -> Self {
+        Self {
+            map: Arc::new(Mutex::new(map)),
+        }
+    }
+}
+
+impl<K> AsyncGridDb<K>
+where
+    K: DbKey + Send + 'static,
+    Archived<K>: Deserialize<K, Infallible> + Ord,
+{
+    /// Runs `f` with exclusive access to the map on a blocking thread.
+    ///
+    /// If `f` panics, the panic is resumed on the awaiting task. The map stays usable from every handle afterwards: each
+    /// operation of [`GridDb`] commits its sled transaction atomically, so a panic can't leave a write half applied.
+    pub async fn run<R>(&self, f: impl FnOnce(&mut GridDb<K>) -> R + Send + 'static) -> R
+    where
+        R: Send + 'static,
+    {
+        let map = self.map.clone();
+        let task = move || f(&mut map.lock().unwrap_or_else(PoisonError::into_inner));
+        match tokio::task::spawn_blocking(task).await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// See [`GridDb::write_working_version`].
+    pub async fn write_working_version(
+        &self,
+        changes: EncodedChanges,
+    ) -> Result<WriteReport, TransactionError<AbortReason>> {
+        self.run(move |map| map.write_working_version(changes))
+            .await
+    }
+
+    /// See [`GridDb::commit_working_version`].
+    pub async fn commit_working_version(
+        &self,
+    ) -> Result<Option<Version>, TransactionError<AbortReason>> {
+        self.run(|map| map.commit_working_version()).await
+    }
+
+    /// See [`GridDb::branch_from_version`].
+    pub async fn branch_from_version(
+        &self,
+        new_parent_version: Version,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        self.run(move |map| map.branch_from_version(new_parent_version))
+            .await
+    }
+
+    /// See [`GridDb::read_working_version_owned`].
+    pub async fn read_working_version_owned(&self, key: K) -> Result<Option<Change>, sled::Error> {
+        self.run(move |map| map.read_working_version_owned(key))
+            .await
+    }
+
+    /// See [`GridDb::flush`].
+    pub async fn flush(&self) -> Result<usize, sled::Error> {
+        self.run(|map| map.flush()).await
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChangeEncoder, DbKey3i32};
+
+    use ilattice::glam::IVec3;
+
+    #[tokio::test]
+    async fn write_commit_read_cycle() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let map = AsyncGridDb::from(GridDb::open(&db, "mymap").unwrap());
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        for byte in [1, 2] {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([byte])));
+            map.write_working_version(encoder.encode()).await.unwrap();
+            map.commit_working_version().await.unwrap();
+        }
+        assert_eq!(
+            map.read_working_version_owned(key).await.unwrap(),
+            Some(Change::Insert(Box::new([2])))
+        );
+
+        let first = map
+            .run(|map| map.cached_meta().grandparent_version)
+            .await
+            .unwrap();
+        map.clone().branch_from_version(first).await.unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key).await.unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+    }
+
+    #[tokio::test]
+    async fn panicking_run_leaves_map_usable() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let map = AsyncGridDb::<DbKey3i32>::from(GridDb::open(&db, "mymap").unwrap());
+
+        let other = map.clone();
+        let panicked = tokio::spawn(async move {
+            other.run(|_| panic!("closure failed")).await;
+        })
+        .await;
+        assert!(panicked.unwrap_err().is_panic());
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key, Change::Insert(Box::new([1])));
+        map.write_working_version(encoder.encode()).await.unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key).await.unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+    }
+}
@@ -1,4 +1,9 @@
-use super::{db::AbortReason, ArchivedChangeIVec, DbKey, EncodedChanges, VersionChanges};
+use super::{
+    db::AbortReason, ArchivedChange, ArchivedChangeIVec, Change, DbKey, EncodedChanges,
+    VersionChanges,
+};
+use crate::meta_tree::{read_backup_keys, write_backup_keys};
+use crate::tree_name;
 
 use sled::transaction::{
     ConflictableTransactionError, TransactionalTree, UnabortableTransactionError,
@@ -6,25 +11,75 @@ use sled::transaction::{
 use sled::Tree;
 use std::collections::{BTreeMap, BTreeSet};
 
-pub fn open_backup_tree<K>(map_name: &str, db: &sled::Db) -> sled::Result<(Tree, BackupKeyCache<K>)>
+/// Loads the [`BackupKeyCache`] from the copy persisted in the `meta_tree`. If there is no such copy, the backup tree is
+/// scanned once and the copy is written.
+pub fn open_backup_tree<K>(
+    map_name: &str,
+    db: &sled::Db,
+    meta_tree: &Tree,
+) -> sled::Result<(Tree, BackupKeyCache<K>)>
+where
+    K: DbKey,
+{
+    let tree = db.open_tree(tree_name(map_name, "backup"))?;
+    let cache = load_backup_key_cache(&tree, meta_tree)?;
+    Ok((tree, cache))
+}
+
+/// Loads the [`BackupKeyCache`] of the backup `tree`, like [`open_backup_tree`].
+pub fn load_backup_key_cache<K>(tree: &Tree, meta_tree: &Tree) -> sled::Result<BackupKeyCache<K>>
+where
+    K: DbKey,
+{
+    if let Some(persisted) = read_persisted_backup_keys(meta_tree)? {
+        Ok(persisted)
+    } else {
+        let cache = scan_backup_keys(tree)?;
+        write_backup_keys(meta_tree, cache.keys.iter().map(|key| key.as_sled_key()))?;
+        Ok(cache)
+    }
+}
+
+pub fn read_persisted_backup_keys<K>(meta_tree: &Tree) -> sled::Result<Option<BackupKeyCache<K>>>
+where
+    K: DbKey,
+{
+    // Every sled key for K has the same length.
+    let key_len = std::mem::size_of::<K::SledKey>();
+    Ok(
+        read_backup_keys(meta_tree, key_len)?.map(|keys| BackupKeyCache {
+            keys: keys.iter().map(|key| K::from_sled_key(key)).collect(),
+        }),
+    )
+}
+
+pub fn scan_backup_keys<K>(tree: &Tree) -> sled::Result<BackupKeyCache<K>>
 where
     K: DbKey,
 {
-    let tree = db.open_tree(format!("{}-backup", map_name))?;
     let mut keys = BTreeSet::default();
     for iter_result in tree.iter() {
         let (key_bytes, _) = iter_result?;
         keys.insert(K::from_sled_key(&key_bytes));
     }
-    Ok((tree, BackupKeyCache { keys }))
+    Ok(BackupKeyCache { keys })
 }
 
+/// The value stored in the backup tree in place of a serialized [`Change::Remove`]. An archived [`Change`] is never empty, so
+/// this can't be confused with any other value.
+const REMOVE_MARKER: &[u8] = &[];
+
+/// [`Change::Remove`]s are stored as the compact [`REMOVE_MARKER`], since mass removals are common.
 pub fn write_changes_to_backup_tree(
     txn: &TransactionalTree,
     changes: EncodedChanges,
 ) -> Result<(), UnabortableTransactionError> {
     for (key_bytes, change) in changes.changes.into_iter() {
-        txn.insert(&key_bytes, change.take_bytes())?;
+        if let ArchivedChange::Remove = change.as_ref() {
+            txn.insert(&key_bytes, REMOVE_MARKER)?;
+        } else {
+            txn.insert(&key_bytes, change.take_bytes())?;
+        }
     }
     Ok(())
 }
@@ -39,8 +94,13 @@ where
     let mut changes = BTreeMap::default();
     for key in keys.keys.iter() {
         if let Some(change) = txn.remove(key.as_sled_key().as_ref())? {
-            let archived_change = unsafe { ArchivedChangeIVec::new(change) };
-            changes.insert(key.clone(), archived_change.deserialize());
+            // Backups written before the REMOVE_MARKER existed still hold a full serialized Change::Remove.
+            let change = if change == REMOVE_MARKER {
+                Change::Remove
+            } else {
+                unsafe { ArchivedChangeIVec::new(change) }.deserialize()
+            };
+            changes.insert(key.clone(), change);
         } else {
             panic!("BUG: failed to get change backup for {:?}", key);
         }
@@ -79,7 +139,7 @@ pub struct BackupKeyCache<K> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Change, ChangeEncoder, DbKey3i32};
+    use crate::{ChangeEncoder, DbKey3i32};
 
     use ilattice::glam::IVec3;
     use sled::transaction::TransactionError;
@@ -87,7 +147,8 @@ mod tests {
     #[test]
     fn write_and_commit_backup() {
         let db = sled::Config::default().temporary(true).open().unwrap();
-        let (tree, mut backup_keys) = open_backup_tree("mymap", &db).unwrap();
+        let meta_tree = db.open_tree("mymap-meta").unwrap();
+        let (tree, mut backup_keys) = open_backup_tree("mymap", &db, &meta_tree).unwrap();
 
         assert!(backup_keys.keys.is_empty());
 
@@ -114,4 +175,40 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn mass_removals_use_compact_marker() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let meta_tree = db.open_tree("mymap-meta").unwrap();
+        let (tree, mut backup_keys) = open_backup_tree("mymap", &db, &meta_tree).unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        for i in 0..100 {
+            let key = DbKey3i32::new(0, IVec3::new(i, 0, 0).into());
+            backup_keys.keys.insert(key);
+            encoder.add_change(key, Change::Remove);
+        }
+        let encoded_changes = encoder.encode();
+
+        let _: Result<_, TransactionError<AbortReason>> = tree.transaction(|txn| {
+            write_changes_to_backup_tree(txn, encoded_changes.clone())?;
+            Ok(())
+        });
+        assert_eq!(tree.len(), 100);
+        for entry in tree.iter() {
+            let (_, value) = entry.unwrap();
+            assert!(value.is_empty());
+        }
+
+        let _: Result<_, TransactionError<AbortReason>> = tree.transaction(|txn| {
+            let reverse_changes = commit_backup(txn, &backup_keys)?;
+            assert_eq!(reverse_changes.changes.len(), 100);
+            assert!(reverse_changes
+                .changes
+                .values()
+                .all(|change| *change == Change::Remove));
+            Ok(())
+        });
+        assert!(tree.is_empty());
+    }
 }
@@ -0,0 +1,170 @@
+use super::{ArchivedChange, ArchivedChangeIVec, ArchivedIVec, Change, DbKey, EncodedChanges};
+use crate::backup_tree::BackupKeyCache;
+use crate::tree_name;
+
+use sled::transaction::{TransactionalTree, UnabortableTransactionError};
+use sled::{IVec, Tree};
+use xxhash_rust::xxh3::xxh3_128;
+
+/// Identifies the content of a blob.
+///
+/// This is a non-cryptographic hash, so content-addressed maps shouldn't store values crafted by an adversary.
+pub type BlobHash = [u8; 16];
+
+/// Suffix of the key holding the blob's bytes.
+const DATA_SUFFIX: u8 = 0;
+/// Suffix of the key holding the blob's reference count.
+const REFS_SUFFIX: u8 = 1;
+
+pub fn open_blob_tree(map_name: &str, db: &sled::Db) -> sled::Result<Tree> {
+    db.open_tree(tree_name(map_name, "blobs"))
+}
+
+pub fn blob_hash(bytes: &[u8]) -> BlobHash {
+    xxh3_128(bytes).to_be_bytes()
+}
+
+fn blob_key(hash: &[u8], suffix: u8) -> [u8; 17] {
+    let mut key = [0; 17];
+    key[..16].copy_from_slice(hash);
+    key[16] = suffix;
+    key
+}
+
+/// Stores the serialized [`Change`] `value` if there isn't already a blob with the same content, then returns the reference
+/// that takes its place in the working tree.
+///
+/// The reference count is not incremented; that happens with [`add_blob_ref`] when the reference is written.
+pub fn store_blob(
+    txn: &TransactionalTree,
+    value: &ArchivedChangeIVec,
+) -> Result<ArchivedChangeIVec, UnabortableTransactionError> {
+    let hash = blob_hash(value.as_bytes());
+    let data_key = blob_key(&hash, DATA_SUFFIX);
+    if txn.get(data_key)?.is_none() {
+        txn.insert(data_key.as_ref(), value.as_bytes())?;
+    }
+    let blob_ref = Change::Insert(Box::new(hash));
+    Ok(unsafe { ArchivedIVec::new(IVec::from(blob_ref.serialize().as_ref())) })
+}
+
+/// The hash held by a blob reference from the working tree, or `None` for a [`Change::Remove`].
+pub fn blob_ref_hash(blob_ref: &ArchivedChange) -> Option<&[u8]> {
+    blob_ref.get_insert_data().map(|hash| &**hash)
+}
+
+pub fn add_blob_ref(
+    txn: &TransactionalTree,
+    hash: &[u8],
+) -> Result<(), UnabortableTransactionError> {
+    let refs_key = blob_key(hash, REFS_SUFFIX);
+    let count = txn.get(refs_key)?.map(|b| decode_count(&b)).unwrap_or(0);
+    txn.insert(refs_key.as_ref(), (count + 1).to_le_bytes().as_ref())?;
+    Ok(())
+}
+
+/// Decrements the reference count of the blob, removing the blob when there are no references left.
+pub fn release_blob_ref(
+    txn: &TransactionalTree,
+    hash: &[u8],
+) -> Result<(), UnabortableTransactionError> {
+    let refs_key = blob_key(hash, REFS_SUFFIX);
+    let count = txn.get(refs_key)?.map(|b| decode_count(&b)).unwrap_or(0);
+    if count <= 1 {
+        txn.remove(refs_key.as_ref())?;
+        txn.remove(blob_key(hash, DATA_SUFFIX).as_ref())?;
+    } else {
+        txn.insert(refs_key.as_ref(), (count - 1).to_le_bytes().as_ref())?;
+    }
+    Ok(())
+}
+
+/// Prepares `changes` to be written to the working tree of a content-addressed map.
+///
+/// If `store` is `true`, the values of `changes` are stored as blobs and replaced with references. Otherwise the values must
+/// already be references, as is the case for archived changes.
+///
+/// Every reference in the returned changes is counted. The old working values of keys that are already in `backup_key_cache`
+/// are about to be overwritten without being backed up, so their references are released. With no `backup_key_cache`, every
+/// old value is released.
+pub fn reference_blobs<K>(
+    working_txn: &TransactionalTree,
+    blob_txn: &TransactionalTree,
+    backup_key_cache: Option<&BackupKeyCache<K>>,
+    changes: EncodedChanges,
+    store: bool,
+) -> Result<EncodedChanges, UnabortableTransactionError>
+where
+    K: DbKey,
+{
+    let mut referenced = Vec::with_capacity(changes.changes.len());
+    for (key_bytes, change) in changes.changes.into_iter() {
+        let change = match change.as_ref() {
+            ArchivedChange::Remove => change,
+            _ if store => store_blob(blob_txn, &change)?,
+            _ => change,
+        };
+        if let Some(hash) = blob_ref_hash(change.as_ref()) {
+            add_blob_ref(blob_txn, hash)?;
+        }
+        // Release after adding, so rewriting the same value never drops the blob.
+        let drops_old_value = match backup_key_cache {
+            Some(cache) => cache.keys.contains(&K::from_sled_key(&key_bytes)),
+            None => true,
+        };
+        if drops_old_value {
+            if let Some(old_value) = working_txn.get(&key_bytes)? {
+                release_dropped_value(blob_txn, old_value)?;
+            }
+        }
+        referenced.push((key_bytes, change));
+    }
+    Ok(EncodedChanges {
+        changes: referenced,
+    })
+}
+
+/// Releases the blob referenced by `value`, a value that is being dropped from the working or backup tree. Empty values and
+/// removes don't reference any blob.
+pub fn release_dropped_value(
+    txn: &TransactionalTree,
+    value: IVec,
+) -> Result<(), UnabortableTransactionError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    let value = unsafe { ArchivedChangeIVec::new(value) };
+    if let Some(hash) = blob_ref_hash(value.as_ref()) {
+        release_blob_ref(txn, hash)?;
+    }
+    Ok(())
+}
+
+/// Resolves a blob reference into the serialized [`Change`] it stands for.
+pub fn read_blob(tree: &Tree, blob_ref: &ArchivedChange) -> sled::Result<ArchivedChangeIVec> {
+    read_blob_by_hash(
+        tree,
+        blob_ref_hash(blob_ref).expect("BUG: blob reference is not an insert"),
+    )
+}
+
+pub fn read_blob_by_hash(tree: &Tree, hash: &[u8]) -> sled::Result<ArchivedChangeIVec> {
+    let bytes = tree
+        .get(blob_key(hash, DATA_SUFFIX))?
+        .expect("BUG: missing blob for reference");
+    Ok(unsafe { ArchivedChangeIVec::new(bytes) })
+}
+
+#[cfg(test)]
+pub fn blob_ref_count(tree: &Tree, hash: &[u8]) -> sled::Result<u64> {
+    Ok(tree
+        .get(blob_key(hash, REFS_SUFFIX))?
+        .map(|b| decode_count(&b))
+        .unwrap_or(0))
+}
+
+fn decode_count(bytes: &[u8]) -> u64 {
+    let mut count_bytes = [0; 8];
+    count_bytes.copy_from_slice(bytes);
+    u64::from_le_bytes(count_bytes)
+}
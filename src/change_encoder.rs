@@ -1,37 +1,164 @@
 use super::{ArchivedIVec, DbKey};
-use crate::{NoSharedAllocSerializer, SmallKeyHashMap};
+use crate::{Level, NoSharedAllocSerializer, SmallKeyHashMap, DEFAULT_SCRATCH_SIZE};
 use rkyv::{
+    check_archived_root,
     ser::{serializers::CoreSerializer, Serializer},
     AlignedBytes, AlignedVec, Archive, Archived, Deserialize, Serialize,
 };
 
 use sled::IVec;
+use std::fmt;
+use std::io::{self, Read};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Takes the place of the [`Compression`] tag at the front of a [`Change::CompressedInsert`] payload to mark a checksummed
+/// payload. It's followed by the checksum and then the checksummed bytes, which start with their own [`Compression`] tag.
+const CHECKSUM_TAG: u8 = 0xFF;
+const CHECKSUM_LEN: usize = 8;
 
 #[derive(Archive, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[archive(check_bytes)]
 pub enum Change {
     Insert(Box<[u8]>),
     Remove,
+    /// A [`Change::Insert`] whose payload was compressed or checksummed by a [`ChangeEncoder`]. The first byte of the payload
+    /// is the [`Compression`] tag, or a checksum marker (see [`Change::with_checksum`]).
+    ///
+    /// Use [`Change::decompress`] to get back the original [`Change::Insert`].
+    CompressedInsert(Box<[u8]>),
 }
 
 impl Change {
+    /// Panics on [`Change::Remove`], and on a [`Change::CompressedInsert`] that can't be decompressed. A
+    /// [`Change::CompressedInsert`] is decompressed first.
     pub fn unwrap_insert(self) -> Box<[u8]> {
+        match self.decompress() {
+            Ok(Change::Insert(x)) => x,
+            Ok(_) => panic!("Unwrapped on Change::Remove"),
+            Err(e) => panic!("Unwrapped a corrupt Change::CompressedInsert: {}", e),
+        }
+    }
+
+    /// Fails like [`Change::decompress`].
+    pub fn map(self, mut f: impl FnMut(Box<[u8]>) -> Box<[u8]>) -> io::Result<Change> {
+        Ok(match self.decompress()? {
+            Change::Insert(x) => Change::Insert(f(x)),
+            other => other,
+        })
+    }
+
+    /// Compresses the payload of a [`Change::Insert`]. Any other change is returned as-is.
+    pub fn compress(self, compression: Compression) -> Change {
+        match (self, compression) {
+            (Change::Insert(x), Compression::Lz4) => {
+                let compressed = lz4_flex::compress_prepend_size(&x);
+                let mut payload = Vec::with_capacity(1 + compressed.len());
+                payload.push(compression.tag());
+                payload.extend_from_slice(&compressed);
+                Change::CompressedInsert(payload.into_boxed_slice())
+            }
+            (other, _) => other,
+        }
+    }
+
+    /// Prepends a checksum of the payload of a [`Change::Insert`] or [`Change::CompressedInsert`], so corruption of the stored
+    /// value can be detected by [`ArchivedChange::verify_checksum`]. The result is a [`Change::CompressedInsert`], whether or
+    /// not the payload is compressed. Removes and changes that already have a checksum are returned as-is.
+    pub fn with_checksum(self) -> Change {
+        let tagged = match self {
+            Change::Insert(x) => {
+                let mut tagged = Vec::with_capacity(1 + x.len());
+                tagged.push(Compression::None.tag());
+                tagged.extend_from_slice(&x);
+                tagged
+            }
+            Change::CompressedInsert(x) if x.first() != Some(&CHECKSUM_TAG) => x.into_vec(),
+            other => return other,
+        };
+        let mut payload = Vec::with_capacity(1 + CHECKSUM_LEN + tagged.len());
+        payload.push(CHECKSUM_TAG);
+        payload.extend_from_slice(&xxh3_64(&tagged).to_le_bytes());
+        payload.extend_from_slice(&tagged);
+        Change::CompressedInsert(payload.into_boxed_slice())
+    }
+
+    /// Turns a [`Change::CompressedInsert`] back into a [`Change::Insert`]. Any other change is returned as-is, so this is
+    /// always safe to call on values written without compression.
+    ///
+    /// Checksums are verified before they're stripped, so a value that decompresses is known to be intact.
+    ///
+    /// Fails with an error of kind [`io::ErrorKind::InvalidData`] if the payload is missing its tag or checksum, doesn't match
+    /// its checksum (wrapping a [`ChecksumMismatch`]), has an unknown tag, or doesn't decompress, which means the stored value
+    /// was corrupted.
+    pub fn decompress(self) -> io::Result<Change> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
         match self {
-            Change::Insert(x) => x,
-            Change::Remove => panic!("Unwrapped on Change::Remove"),
+            Change::CompressedInsert(x) => {
+                let (&tag, compressed) = x
+                    .split_first()
+                    .ok_or_else(|| invalid("compressed payload is missing its tag".into()))?;
+                if tag == CHECKSUM_TAG {
+                    let tagged = compressed.get(CHECKSUM_LEN..).ok_or_else(|| {
+                        invalid("checksummed payload is missing its checksum".into())
+                    })?;
+                    if compressed[..CHECKSUM_LEN] != xxh3_64(tagged).to_le_bytes() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch));
+                    }
+                    return Change::CompressedInsert(tagged.into()).decompress();
+                }
+                match Compression::from_tag(tag) {
+                    Some(Compression::Lz4) => lz4_flex::decompress_size_prepended(compressed)
+                        .map(|bytes| Change::Insert(bytes.into_boxed_slice()))
+                        .map_err(|e| invalid(format!("corrupt LZ4 payload: {}", e))),
+                    Some(Compression::None) => Ok(Change::Insert(compressed.into())),
+                    None => Err(invalid(format!("unknown compression tag {}", tag))),
+                }
+            }
+            other => Ok(other),
         }
     }
+}
+
+/// The compression scheme applied by a [`ChangeEncoder`] to [`Change::Insert`] payloads.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::None
+    }
+}
 
-    pub fn map(self, mut f: impl FnMut(Box<[u8]>) -> Box<[u8]>) -> Change {
+impl Compression {
+    /// The byte stored at the front of a [`Change::CompressedInsert`] payload.
+    pub const fn tag(self) -> u8 {
         match self {
-            Change::Insert(x) => Change::Insert(f(x)),
-            Change::Remove => Change::Remove,
+            Self::None => 0,
+            Self::Lz4 => 1,
+        }
+    }
+
+    pub const fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::None),
+            1 => Some(Self::Lz4),
+            _ => None,
         }
     }
 }
 
 impl Change {
     pub fn serialize(&self) -> AlignedVec {
-        let mut serializer = NoSharedAllocSerializer::<8912>::default();
+        self.serialize_with_scratch::<DEFAULT_SCRATCH_SIZE>()
+    }
+
+    /// Same as [`Change::serialize`], but with `N` bytes of scratch space, which avoids falling back to allocation for large
+    /// payloads.
+    pub fn serialize_with_scratch<const N: usize>(&self) -> AlignedVec {
+        let mut serializer = NoSharedAllocSerializer::<N>::default();
         serializer.serialize_value(self).unwrap();
         serializer.into_serializer().into_inner()
     }
@@ -46,26 +173,120 @@ impl Change {
     }
 }
 
+/// The payload of a checksummed [`Change`] doesn't match its checksum, so the stored value was corrupted.
+///
+/// Reads of the working version report this as a [`sled::Error::Io`] of kind [`io::ErrorKind::InvalidData`] wrapping this
+/// error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChecksumMismatch;
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stored value doesn't match its checksum")
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+impl From<ChecksumMismatch> for sled::Error {
+    fn from(e: ChecksumMismatch) -> Self {
+        sled::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
 impl ArchivedChange {
+    /// Checks the payload against its checksum, if it has one (see [`Change::with_checksum`]).
+    pub fn verify_checksum(&self) -> Result<(), ChecksumMismatch> {
+        let payload = match self {
+            Self::CompressedInsert(payload) => payload,
+            Self::Insert(_) | Self::Remove => return Ok(()),
+        };
+        match payload.split_first() {
+            Some((&CHECKSUM_TAG, rest)) if rest.len() >= CHECKSUM_LEN => {
+                let (checksum, tagged) = rest.split_at(CHECKSUM_LEN);
+                if checksum == xxh3_64(tagged).to_le_bytes() {
+                    Ok(())
+                } else {
+                    Err(ChecksumMismatch)
+                }
+            }
+            Some((&CHECKSUM_TAG, _)) => Err(ChecksumMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the uncompressed payload of an [`ArchivedChange::Insert`]. Compressed payloads can't be borrowed, so this
+    /// returns `None` for [`ArchivedChange::CompressedInsert`].
     pub fn get_insert_data(&self) -> Option<&Archived<Box<[u8]>>> {
         match self {
             Self::Insert(data) => Some(data),
-            Self::Remove => None,
+            Self::Remove | Self::CompressedInsert(_) => None,
         }
     }
 }
 
+/// Serializes a [`Change::Insert`] of the `len` bytes read from `reader` without holding the payload anywhere else.
+///
+/// `rkyv` writes the payload first, followed by the archived [`Change`] (aligned for its type) pointing back at it. We can't
+/// ask `rkyv` to resolve a box without the boxed value, so the archived change is written by hand and then validated.
+fn serialize_insert_from_reader(mut reader: impl Read, len: usize) -> io::Result<AlignedVec> {
+    let root_align = std::mem::align_of::<ArchivedChange>();
+    let root_pos = (len + root_align - 1) / root_align * root_align;
+    let root_size = std::mem::size_of::<ArchivedChange>();
+    let ptr_pos = i32::try_from(root_pos + 4)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "payload is too large"))?;
+    let mut bytes = AlignedVec::with_capacity(root_pos + root_size);
+
+    let mut buf = [0; 8192];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = &mut buf[..remaining.min(buf.len())];
+        reader.read_exact(chunk)?;
+        bytes.extend_from_slice(chunk);
+        remaining -= chunk.len();
+    }
+    bytes.extend_from_slice(&[0; 16][..root_pos - len]);
+
+    // The variant tag and its padding, then the relative pointer to the payload and the payload's length.
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&(-ptr_pos).to_ne_bytes());
+    bytes.extend_from_slice(&(len as u32).to_ne_bytes());
+
+    let is_valid = bytes.len() == root_pos + root_size
+        && matches!(
+            check_archived_root::<Change>(&bytes),
+            Ok(ArchivedChange::Insert(payload)) if payload.len() == len
+        );
+    assert!(
+        is_valid,
+        "BUG: streamed insert doesn't match the archived layout of Change"
+    );
+    Ok(bytes)
+}
+
 /// Creates an [`EncodedChanges`].
 ///
 /// Prevents duplicates, keeping the latest change. Also sorts the changes by Morton order for efficient DB insertion.
+///
+/// Optionally compresses [`Change::Insert`] payloads; see [`ChangeEncoder::with_compression`].
 pub struct ChangeEncoder<K> {
-    added_changes: SmallKeyHashMap<K, Change>,
+    added_changes: SmallKeyHashMap<K, PendingChange>,
+    compression: Compression,
+    checksums: bool,
+}
+
+enum PendingChange {
+    Change(Change),
+    /// Already serialized by [`ChangeEncoder::add_change_streaming`].
+    Serialized(ArchivedChangeIVec),
 }
 
 impl<K> Default for ChangeEncoder<K> {
     fn default() -> Self {
         Self {
             added_changes: Default::default(),
+            compression: Compression::None,
+            checksums: false,
         }
     }
 }
@@ -74,21 +295,107 @@ impl<K> ChangeEncoder<K>
 where
     K: DbKey,
 {
+    /// Every [`Change::Insert`] added to this encoder will be stored as a [`Change::CompressedInsert`] using `compression`.
+    pub fn with_compression(compression: Compression) -> Self {
+        Self {
+            compression,
+            ..Default::default()
+        }
+    }
+
+    /// Stores a checksum with every [`Change::Insert`] added to this encoder (see [`Change::with_checksum`]), so reads of the
+    /// working version fail with [`ChecksumMismatch`] instead of returning a corrupted value.
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Preallocates room for `capacity` distinct keys.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            added_changes: SmallKeyHashMap::with_capacity(capacity),
+            ..Default::default()
+        }
+    }
+
+    /// Makes room for at least `additional` more distinct keys.
+    pub fn reserve(&mut self, additional: usize) {
+        self.added_changes.reserve(additional);
+    }
+
     pub fn add_change(&mut self, key: K, change: Change) {
-        self.added_changes.insert(key, change);
+        self.added_changes
+            .insert(key, PendingChange::Change(change));
+    }
+
+    /// Adds a [`Change::Insert`] of the `len` bytes read from `reader`, serializing the payload as it's read.
+    ///
+    /// [`ChangeEncoder::add_change`] holds the whole payload until [`ChangeEncoder::encode`], which then holds the payload and
+    /// its serialized copy at the same time. This path reads the payload in small chunks straight into the buffer it's
+    /// serialized in, so there is no separate copy of the whole payload. That buffer is copied into the `IVec` held by the
+    /// encoder and dropped right away, like the serialized copy of any other change. The stored bytes are identical to those
+    /// of [`ChangeEncoder::add_change`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if this encoder compresses or checksums its values, since that needs the
+    /// whole payload at once, and with [`io::ErrorKind::UnexpectedEof`] if `reader` ends before `len` bytes.
+    pub fn add_change_streaming(
+        &mut self,
+        key: K,
+        reader: impl Read,
+        len: usize,
+    ) -> io::Result<()> {
+        if self.compression != Compression::None || self.checksums {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "streamed inserts can't be compressed or checksummed",
+            ));
+        }
+        let bytes = serialize_insert_from_reader(reader, len)?;
+        let change = unsafe { ArchivedIVec::new(IVec::from(bytes.as_ref())) };
+        self.added_changes
+            .insert(key, PendingChange::Serialized(change));
+        Ok(())
+    }
+
+    /// Same as [`ChangeEncoder::add_change`] for the key of the node at `coords` on `level` (see [`DbKey::from_coords`]).
+    pub fn add_change_at(&mut self, level: Level, coords: K::Coords, change: Change) {
+        self.add_change(K::from_coords(level, coords), change);
+    }
+
+    /// The number of distinct keys added so far.
+    pub fn len(&self) -> usize {
+        self.added_changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_changes.is_empty()
     }
 
     /// Sorts the changes by Morton key and converts them to `IVec` key-value pairs for `sled`.
     pub fn encode(self) -> EncodedChanges {
+        self.encode_with_scratch::<DEFAULT_SCRATCH_SIZE>()
+    }
+
+    /// Same as [`ChangeEncoder::encode`], but each value is serialized with `N` bytes of scratch space. See
+    /// [`Change::serialize_with_scratch`].
+    pub fn encode_with_scratch<const N: usize>(self) -> EncodedChanges {
         // Serialize values.
+        let (compression, checksums) = (self.compression, self.checksums);
         let mut changes: Vec<_> = self
             .added_changes
             .into_iter()
-            .map(|(key, change)| {
-                (key, unsafe {
-                    // PERF: sad that we can't serialize directly into an IVec
-                    ArchivedIVec::new(IVec::from(change.serialize().as_ref()))
-                })
+            .map(|(key, change)| match change {
+                PendingChange::Change(change) => {
+                    let mut change = change.compress(compression);
+                    if checksums {
+                        change = change.with_checksum();
+                    }
+                    (key, unsafe {
+                        // PERF: sad that we can't serialize directly into an IVec
+                        ArchivedIVec::new(IVec::from(change.serialize_with_scratch::<N>().as_ref()))
+                    })
+                }
+                PendingChange::Serialized(change) => (key, change),
             })
             .collect();
 
@@ -105,6 +412,20 @@ where
     }
 }
 
+/// Same as calling [`ChangeEncoder::add_change`] for each item, so later changes to a key replace earlier ones.
+impl<K> Extend<(K, Change)> for ChangeEncoder<K>
+where
+    K: DbKey,
+{
+    fn extend<I: IntoIterator<Item = (K, Change)>>(&mut self, changes: I) {
+        self.added_changes.extend(
+            changes
+                .into_iter()
+                .map(|(key, change)| (key, PendingChange::Change(change))),
+        );
+    }
+}
+
 /// A set of [Change]s to be atomically applied to a [`GridDb`](crate::GridDb).
 ///
 /// Should be created with a [`ChangeEncoder`], which is guaranteed to drop duplicate changes on the same key, keeping only the
@@ -116,8 +437,8 @@ pub struct EncodedChanges {
 
 /// We use this format for all changes stored in the working tree and backup tree.
 ///
-/// Any values written to the working tree must be [`Change::Insert`] variants, but [`Change::Remove`]s are allowed and
-/// necessary inside the backup tree.
+/// Any values written to the working tree must be [`Change::Insert`] or [`Change::CompressedInsert`] variants, but
+/// [`Change::Remove`]s are allowed and necessary inside the backup tree, where they are stored as a compact empty marker.
 ///
 /// By using the same format for values in both trees, we don't need to re-serialize them when moving any entry from the working
 /// tree to the backup tree.
@@ -134,7 +455,9 @@ pub type ArchivedChangeIVec = ArchivedIVec<Change>;
 mod tests {
     use super::*;
     use crate::archived_buf::ArchivedBuf;
+    use crate::DbKey3i32;
 
+    use ilattice::glam::IVec3;
     use sled::IVec;
 
     #[test]
@@ -153,4 +476,210 @@ mod tests {
         let deserialized = serialized.deserialize();
         assert_eq!(deserialized, original);
     }
+
+    fn encode_and_decode(compression: Compression, payload: Box<[u8]>) -> (Change, Change) {
+        let mut encoder = ChangeEncoder::with_compression(compression);
+        encoder.add_change(
+            DbKey3i32::new(0, IVec3::ZERO.into()),
+            Change::Insert(payload),
+        );
+        let (_key, stored) = encoder.encode().changes.pop().unwrap();
+        let stored = stored.deserialize();
+        let decoded = stored.clone().decompress().unwrap();
+        (stored, decoded)
+    }
+
+    #[test]
+    fn lz4_round_trip() {
+        let payload: Box<[u8]> = vec![0; 4096].into();
+        let (stored, decoded) = encode_and_decode(Compression::Lz4, payload.clone());
+        match &stored {
+            Change::CompressedInsert(bytes) => {
+                assert_eq!(bytes[0], Compression::Lz4.tag());
+                assert!(bytes.len() < payload.len());
+            }
+            other => panic!("Expected compressed insert, got {:?}", other),
+        }
+        assert_eq!(decoded, Change::Insert(payload));
+    }
+
+    #[test]
+    fn lz4_round_trip_empty_payload() {
+        let (stored, decoded) = encode_and_decode(Compression::Lz4, Box::new([]));
+        assert!(matches!(stored, Change::CompressedInsert(_)));
+        assert_eq!(decoded, Change::Insert(Box::new([])));
+    }
+
+    #[test]
+    fn uncompressed_round_trip() {
+        for payload in [vec![0; 4096].into(), Box::new([]) as Box<[u8]>] {
+            let (stored, decoded) = encode_and_decode(Compression::None, payload.clone());
+            assert_eq!(stored, Change::Insert(payload.clone()));
+            assert_eq!(decoded, Change::Insert(payload));
+        }
+    }
+
+    #[test]
+    fn serialize_with_any_scratch_size() {
+        let payload: Box<[u8]> = (0..4 * DEFAULT_SCRATCH_SIZE).map(|i| i as u8).collect();
+        for change in [Change::Insert(Box::new([1, 2, 3])), Change::Insert(payload)] {
+            for bytes in [
+                change.serialize(),
+                change.serialize_with_scratch::<64>(),
+                change.serialize_with_scratch::<{ 8 * DEFAULT_SCRATCH_SIZE }>(),
+            ] {
+                let archived = unsafe { ArchivedIVec::<Change>::new(IVec::from(bytes.as_ref())) };
+                assert_eq!(archived.deserialize(), change);
+            }
+        }
+    }
+
+    #[test]
+    fn extend_keeps_latest_change_per_key() {
+        let [key1, key2] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let changes = [
+            (key1, Change::Insert(Box::new([1]))),
+            (key2, Change::Insert(Box::new([2]))),
+            (key1, Change::Remove),
+        ];
+
+        let mut one_by_one = ChangeEncoder::default();
+        for (key, change) in changes.clone() {
+            one_by_one.add_change(key, change);
+        }
+        let mut extended = ChangeEncoder::with_capacity(100);
+        assert!(extended.is_empty());
+        extended.add_change(key2, Change::Insert(Box::new([0])));
+        extended.extend(changes);
+        assert_eq!(extended.len(), 2);
+
+        let decode = |encoder: ChangeEncoder<DbKey3i32>| -> Vec<_> {
+            encoder
+                .encode()
+                .changes
+                .into_iter()
+                .map(|(key, change)| (key, change.deserialize()))
+                .collect()
+        };
+        let expected = vec![
+            (IVec::from(key1.as_sled_key().as_ref()), Change::Remove),
+            (
+                IVec::from(key2.as_sled_key().as_ref()),
+                Change::Insert(Box::new([2])),
+            ),
+        ];
+        assert_eq!(decode(one_by_one), expected);
+        assert_eq!(decode(extended), expected);
+    }
+
+    #[test]
+    fn compression_leaves_removes_alone() {
+        assert_eq!(Change::Remove.compress(Compression::Lz4), Change::Remove);
+        assert_eq!(Change::Remove.decompress().unwrap(), Change::Remove);
+    }
+
+    #[test]
+    fn decompressing_corrupt_payloads_fails() {
+        let lz4 = Change::Insert(vec![5; 256].into()).compress(Compression::Lz4);
+        let truncated = match lz4 {
+            Change::CompressedInsert(bytes) => Change::CompressedInsert(bytes[..8].into()),
+            other => panic!("Expected compressed insert, got {:?}", other),
+        };
+        for corrupt in [
+            truncated,
+            Change::CompressedInsert(Box::new([])),
+            Change::CompressedInsert(Box::new([7, 1, 2, 3])),
+            Change::CompressedInsert(Box::new([CHECKSUM_TAG, 1, 2])),
+        ] {
+            let error = corrupt.decompress().unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn checksummed_round_trip() {
+        let payload: Box<[u8]> = (0..4096).map(|i| (i % 7) as u8).collect();
+        for compression in [Compression::None, Compression::Lz4] {
+            let mut encoder = ChangeEncoder::with_compression(compression).with_checksums(true);
+            encoder.add_change(
+                DbKey3i32::new(0, IVec3::ZERO.into()),
+                Change::Insert(payload.clone()),
+            );
+            let (_key, stored) = encoder.encode().changes.pop().unwrap();
+            assert_eq!(stored.as_ref().verify_checksum(), Ok(()));
+            let stored = stored.deserialize();
+            match &stored {
+                Change::CompressedInsert(bytes) => assert_eq!(bytes[0], CHECKSUM_TAG),
+                other => panic!("Expected checksummed insert, got {:?}", other),
+            }
+            assert_eq!(stored.clone().with_checksum(), stored);
+            assert_eq!(
+                stored.decompress().unwrap(),
+                Change::Insert(payload.clone())
+            );
+        }
+        assert_eq!(Change::Remove.with_checksum(), Change::Remove);
+    }
+
+    #[test]
+    fn verify_checksum_detects_corruption() {
+        let change = Change::Insert(Box::new([1, 2, 3])).with_checksum();
+        let mut bytes = change.serialize().to_vec();
+        // The payload comes first in the archive; flip a byte of the checksummed data after the tag and checksum.
+        bytes[1 + CHECKSUM_LEN + 1] ^= 1;
+        let corrupted = unsafe { ArchivedIVec::<Change>::new(IVec::from(bytes)) };
+        assert_eq!(corrupted.as_ref().verify_checksum(), Err(ChecksumMismatch));
+        let error = corrupted.deserialize().decompress().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.get_ref().unwrap().is::<ChecksumMismatch>());
+
+        // Values without checksums have nothing to verify.
+        let plain = Change::Insert(Box::new([1, 2, 3])).serialize();
+        let plain = unsafe { ArchivedIVec::<Change>::new(IVec::from(plain.as_ref())) };
+        assert_eq!(plain.as_ref().verify_checksum(), Ok(()));
+    }
+
+    #[test]
+    fn streamed_insert_matches_regular_insert() {
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        for len in [0, 1, 3, 4, 5, 4099] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+
+            let mut regular = ChangeEncoder::default();
+            regular.add_change(key, Change::Insert(payload.clone().into()));
+            let mut streamed = ChangeEncoder::default();
+            streamed
+                .add_change_streaming(key, payload.as_slice(), len)
+                .unwrap();
+
+            let (_, regular) = regular.encode().changes.pop().unwrap();
+            let (_, streamed) = streamed.encode().changes.pop().unwrap();
+            assert_eq!(streamed.as_bytes(), regular.as_bytes());
+        }
+    }
+
+    #[test]
+    fn streamed_insert_fails_on_short_reader() {
+        let mut encoder = ChangeEncoder::default();
+        let result = encoder.add_change_streaming(
+            DbKey3i32::new(0, IVec3::ZERO.into()),
+            [1u8, 2, 3].as_slice(),
+            4,
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert!(encoder.is_empty());
+    }
+
+    #[test]
+    fn streamed_insert_fails_when_encoding_values() {
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        for mut encoder in [
+            ChangeEncoder::with_compression(Compression::Lz4),
+            ChangeEncoder::default().with_checksums(true),
+        ] {
+            let result = encoder.add_change_streaming(key, [1u8, 2, 3].as_slice(), 3);
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            assert!(encoder.is_empty());
+        }
+    }
 }
@@ -1,390 +1,4996 @@
-use crate::backup_tree::{
-    clear_backup, commit_backup, open_backup_tree, write_changes_to_backup_tree, BackupKeyCache,
-};
-use crate::change_encoder::{Change, ChangeEncoder, EncodedChanges};
-use crate::db_key::DbKey;
-use crate::meta_tree::{open_meta_tree, write_meta, GridDbMetadata};
-use crate::version_change_tree::{
-    archive_version, open_version_change_tree, remove_archived_version, VersionChanges,
-};
-use crate::version_graph_tree::{
-    find_path_between_versions, link_version, open_version_graph_tree, VersionNode,
-};
-use crate::working_tree::{open_working_tree, write_changes_to_working_tree};
-use crate::{ArchivedChangeIVec, ArchivedIVec, Version};
-
-use itertools::Itertools;
-use rkyv::{Archived, Deserialize, Infallible};
-use sled::transaction::{abort, TransactionError};
-use sled::{IVec, Transactional, Tree};
-use std::collections::BTreeSet;
-
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum AbortReason {
-    /// Failed to find a path from the one parent version to another.
-    NoPathExists,
-    /// Failed to find a path from a version node to the root ancestor. (Missing link).
-    NoPathExistsToRoot,
-    /// Tried to reference [`VersionChanges`] that don't exist in the change tree.
-    MissingVersionChanges,
-}
-
-/// # Quadtree/Octree Database
-///
-/// This database supports CRUD operations on `(DbKey, [u8])` pairs as well as a versioned log of changes.
-///
-/// ## Implementation
-///
-/// All user data is stored in three [`sled::Tree`]s.
-///
-/// ### Working Tree
-///
-/// One tree is used for the *working* [`Version`] of the map, and it stores all of the `[u8]` data for the working
-/// version. All new changes are written to this tree.
-///
-/// ### Backup Tree
-///
-/// As new changes are written, the old values are moved into the "backup tree." The backup tree is just a persistent buffer
-/// that eventually gets archived when the working version is committed.
-///
-/// ### Version Tree
-///
-/// Archived versions get an entry in the "version tree." This stores an actual tree structure where each node has a parent
-/// version (except for the root version). To "revert" to a parent version, all of the backed up values must be re-applied in
-/// reverse order, while the corresponding newer values are archived. By transitivity, any archived version can be reached from
-/// the current working version.
-pub struct GridDb<K> {
-    meta_tree: Tree,
-    working_tree: Tree,
-    backup_tree: Tree,
-
-    // We keep the change tree and graph trees separate so that finding a path between versions does not require reading all of
-    // the changes associated with each version.
-    version_change_tree: Tree,
-    version_graph_tree: Tree,
-
-    /// HACK: We only have this type to work around sled's lack of transactional iteration. When archiving a version, we iterate
-    /// over this set of keys and put the entries into the archive.
-    backup_key_cache: BackupKeyCache<K>,
-    // Zero-copy isn't super important for this tiny struct, so we just copy it for convenience.
-    cached_meta: GridDbMetadata,
-}
-
-impl<K> GridDb<K>
-where
-    K: DbKey,
-    Archived<K>: Deserialize<K, Infallible> + Ord,
-{
-    /// Opens the database. On first open, a single working version will be created with no parent version.
-    pub fn open(db: &sled::Db, map_name: &str) -> Result<Self, TransactionError<AbortReason>> {
-        let (meta_tree, cached_meta) = open_meta_tree(map_name, db)?;
-        let version_change_tree = open_version_change_tree(map_name, db)?;
-        let version_graph_tree = open_version_graph_tree(map_name, db)?;
-        let (backup_tree, backup_key_cache) = open_backup_tree(map_name, db)?;
-        let working_tree = open_working_tree(map_name, db)?;
-
-        Ok(Self {
-            meta_tree,
-            working_tree,
-            backup_tree,
-            version_change_tree,
-            version_graph_tree,
-            backup_key_cache,
-            cached_meta,
-        })
-    }
-
-    pub fn cached_meta(&self) -> &GridDbMetadata {
-        &self.cached_meta
-    }
-
-    /// Writes `changes` to the working version and stores the old values in the backup tree.
-    pub fn write_working_version(
-        &mut self,
-        changes: EncodedChanges,
-    ) -> Result<(), TransactionError> {
-        log::trace!("Writing to {:?}", self.cached_meta.working_version);
-        let Self {
-            working_tree,
-            backup_tree,
-            backup_key_cache,
-            ..
-        } = self;
-        let new_backup_keys: Vec<_> =
-            (&*working_tree, &*backup_tree).transaction(|(working_txn, backup_txn)| {
-                let reverse_changes =
-                    write_changes_to_working_tree(working_txn, backup_key_cache, changes.clone())?;
-                let new_backup_keys = reverse_changes
-                    .changes
-                    .iter()
-                    .map(|(key, _)| K::from_sled_key(key))
-                    .collect();
-                write_changes_to_backup_tree(backup_txn, reverse_changes)?;
-                Ok(new_backup_keys)
-            })?;
-        // Transaction succeeded, so add the new keys to the backup cache.
-        for key in new_backup_keys.into_iter() {
-            debug_assert!(!backup_key_cache.keys.contains(&key));
-            backup_key_cache.keys.insert(key);
-        }
-        Ok(())
-    }
-
-    /// Reads the compressed bytes of the chunk at `key` for the working version.
-    pub fn read_working_version(&self, key: K) -> Result<Option<ArchivedChangeIVec>, sled::Error> {
-        let bytes = self
-            .working_tree
-            .get(IVec::from(key.as_sled_key().as_ref()))?;
-        Ok(bytes.map(|b| unsafe { ArchivedIVec::<Change>::new(b) }))
-    }
-
-    /// Archives the backup tree entries into a [`VersionChanges`] that gets serialized and stored in the version change tree
-    /// with the current working [`Version`]. A new working version is generated and the old working version becomes the parent
-    /// version.
-    ///
-    /// Nothing happens if the working version has no changes.
-    pub fn commit_working_version(&mut self) -> Result<(), TransactionError<AbortReason>> {
-        if self.backup_key_cache.keys.is_empty() {
-            return Ok(());
-        }
-
-        log::trace!(
-            "Committing non-empty {:?}",
-            self.cached_meta.working_version
-        );
-
-        let new_meta = (
-            &self.backup_tree,
-            &self.version_graph_tree,
-            &self.version_change_tree,
-            &self.meta_tree,
-        )
-            .transaction(|(backup_txn, graph_txn, changes_txn, meta_txn)| {
-                if let Some(parent) = self.cached_meta.parent_version {
-                    log::trace!("Archiving {:?} from backup", parent);
-                    archive_version(
-                        changes_txn,
-                        parent,
-                        &commit_backup(backup_txn, &self.backup_key_cache)?,
-                    )?;
-                } else {
-                    // We only need to do this once, but it's important for correctness.
-                    clear_backup(backup_txn, &self.backup_key_cache)?;
-                }
-                link_version(
-                    graph_txn,
-                    self.cached_meta.working_version,
-                    VersionNode {
-                        parent_version: self.cached_meta.parent_version,
-                    },
-                )?;
-                let new_meta = GridDbMetadata {
-                    grandparent_version: self.cached_meta.parent_version,
-                    parent_version: Some(self.cached_meta.working_version),
-                    working_version: Version::new(graph_txn.generate_id()?),
-                };
-                write_meta(meta_txn, &new_meta)?;
-                Ok(new_meta)
-            })?;
-        self.backup_key_cache.keys.clear();
-        self.cached_meta = new_meta;
-        Ok(())
-    }
-
-    /// Sets the parent version to `new_parent_version` and generates a new (empty) working child version.
-    ///
-    /// This will always `commit_working_version` before migrating to a new parent. If there is no parent for the current
-    /// working version, then nothing happens.
-    pub fn branch_from_version(
-        &mut self,
-        new_parent_version: Version,
-    ) -> Result<(), TransactionError<AbortReason>> {
-        // After committing, we may end up with a new empty working version. But it's not linked into the graph yet. We can just
-        // abandon it, since it is empty.
-        self.commit_working_version()?;
-
-        let old_meta = self.cached_meta;
-
-        if let Some(old_parent_version) = old_meta.parent_version {
-            let new_meta = (
-                &self.meta_tree,
-                &self.version_graph_tree,
-                &self.version_change_tree,
-                &self.working_tree,
-            )
-                .transaction(|(meta_txn, graph_txn, change_txn, working_txn)| {
-                    // Apply the archived changes from all versions between the old parent version and the new parent version,
-                    // leaving behind the inverse changes.
-                    let path = find_path_between_versions(
-                        graph_txn,
-                        old_parent_version,
-                        new_parent_version,
-                    )?;
-                    let empty_backup_keys: BackupKeyCache<K> = BackupKeyCache {
-                        keys: BTreeSet::default(),
-                    };
-                    log::trace!(
-                        "Migrating from parent {:?} to parent {:?}",
-                        old_parent_version,
-                        new_parent_version
-                    );
-                    for (&prev_version, &next_version) in path.path.iter().tuple_windows() {
-                        if let Some(changes) =
-                            remove_archived_version::<K>(change_txn, next_version)?
-                        {
-                            let mut encoder = ChangeEncoder::default();
-                            for (key, change) in changes.as_ref().changes.iter() {
-                                let key: K = key.deserialize(&mut Infallible).unwrap();
-                                // PERF: in principle we should be able to copy the compressed bytes directly from the archived
-                                // change, but the types aren't set up for that yet
-                                let change = change.deserialize(&mut Infallible).unwrap();
-                                encoder.add_change(key, change);
-                            }
-                            let reverse_changes = write_changes_to_working_tree(
-                                working_txn,
-                                &empty_backup_keys,
-                                encoder.encode(),
-                            )?;
-                            let prev_version_changes = VersionChanges::<K>::from(&reverse_changes);
-                            log::trace!("Archiving {:?} from working tree", prev_version,);
-                            archive_version(change_txn, prev_version, &prev_version_changes)?;
-                        } else {
-                            return abort(AbortReason::MissingVersionChanges);
-                        }
-                    }
-                    let new_working_version = Version::new(graph_txn.generate_id()?);
-                    let new_meta = GridDbMetadata {
-                        grandparent_version: path.end_parent,
-                        parent_version: Some(new_parent_version),
-                        working_version: new_working_version,
-                    };
-                    write_meta(meta_txn, &new_meta)?;
-                    Ok(new_meta)
-                })?;
-            self.cached_meta = new_meta;
-        }
-
-        Ok(())
-    }
-}
-
-// ████████╗███████╗███████╗████████╗
-// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
-//    ██║   █████╗  ███████╗   ██║
-//    ██║   ██╔══╝  ╚════██║   ██║
-//    ██║   ███████╗███████║   ██║
-//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::DbKey3i32;
-
-    use ilattice::glam::IVec3;
-
-    #[test]
-    fn write_and_read_changes_same_version() {
-        let db = sled::Config::default().temporary(true).open().unwrap();
-        let mut map = GridDb::open(&db, "mymap").unwrap();
-
-        let chunk_key = DbKey3i32::new(1, IVec3::ZERO.into());
-        let mut encoder = ChangeEncoder::default();
-        encoder.add_change(chunk_key, Change::Insert(Box::new([0])));
-        map.write_working_version(encoder.encode()).unwrap();
-
-        let chunk_compressed_bytes = map.read_working_version(chunk_key).unwrap().unwrap();
-        assert_eq!(
-            chunk_compressed_bytes.deserialize(),
-            Change::Insert(Box::new([0]))
-        );
-    }
-
-    #[test]
-    fn commit_empty_working_version_does_nothing() {
-        let db = sled::Config::default().temporary(true).open().unwrap();
-        let mut map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
-
-        assert_eq!(
-            map.cached_meta(),
-            &GridDbMetadata {
-                grandparent_version: None,
-                parent_version: None,
-                working_version: Version::new(0),
-            }
-        );
-
-        map.commit_working_version().unwrap();
-
-        assert_eq!(
-            map.cached_meta(),
-            &GridDbMetadata {
-                grandparent_version: None,
-                parent_version: None,
-                working_version: Version::new(0),
-            }
-        );
-    }
-
-    #[test]
-    fn commit_multiple_versions_with_changes_and_branch() {
-        let db = sled::Config::default().temporary(true).open().unwrap();
-        let mut map = GridDb::open(&db, "mymap").unwrap();
-
-        let chunk_key1 = DbKey3i32::new(1, IVec3::ZERO.into());
-        let mut encoder = ChangeEncoder::default();
-        encoder.add_change(chunk_key1, Change::Insert(Box::new([0])));
-        map.write_working_version(encoder.encode()).unwrap();
-
-        let v0 = map.cached_meta().working_version;
-        map.commit_working_version().unwrap();
-
-        // Undo the previous change.
-        let mut encoder = ChangeEncoder::default();
-        encoder.add_change(chunk_key1, Change::Remove);
-        map.write_working_version(encoder.encode()).unwrap();
-
-        let v1 = map.cached_meta().working_version;
-        map.commit_working_version().unwrap();
-
-        assert_eq!(
-            map.cached_meta(),
-            &GridDbMetadata {
-                working_version: Version::new(2),
-                parent_version: Some(v1),
-                grandparent_version: Some(v0),
-            }
-        );
-
-        // We removed the entry in this version.
-        assert_eq!(map.read_working_version(chunk_key1).unwrap(), None);
-
-        // But we can bring it back by reverting to v0.
-        map.branch_from_version(v0).unwrap();
-
-        let expected_insert = Ok(Some(unsafe {
-            ArchivedChangeIVec::new(IVec::from(
-                Change::Insert(Box::new([0])).serialize().as_ref(),
-            ))
-        }));
-
-        assert_eq!(map.read_working_version(chunk_key1), expected_insert);
-
-        // Commit changes to the branch.
-        let chunk_key2 = DbKey3i32::new(2, IVec3::ZERO.into());
-        let mut encoder = ChangeEncoder::default();
-        encoder.add_change(chunk_key2, Change::Insert(Box::new([0])));
-        map.write_working_version(encoder.encode()).unwrap();
-        let v2 = map.cached_meta().working_version;
-        map.commit_working_version().unwrap();
-
-        // Branch from a sibling version.
-        map.branch_from_version(v1).unwrap();
-        assert_eq!(map.read_working_version(chunk_key1), Ok(None));
-        assert_eq!(map.read_working_version(chunk_key2).unwrap(), None);
-
-        // And back.
-        map.branch_from_version(v2).unwrap();
-        assert_eq!(map.read_working_version(chunk_key1), expected_insert);
-        assert_eq!(map.read_working_version(chunk_key2), expected_insert);
-    }
-}
+use crate::backup_tree::{
+    clear_backup, commit_backup, load_backup_key_cache, open_backup_tree, scan_backup_keys,
+    write_changes_to_backup_tree, BackupKeyCache,
+};
+use crate::blob_tree::{
+    blob_ref_hash, open_blob_tree, read_blob_by_hash, reference_blobs, release_blob_ref,
+    release_dropped_value,
+};
+use crate::change_encoder::{ArchivedChange, Change, ChangeEncoder, Compression, EncodedChanges};
+use crate::db_key::DbKey;
+use crate::export::{
+    read_header, read_section, write_header, write_section, ExportError, ExportSection,
+    ImportError, CONTENT_ADDRESSED_FLAG, TREE_SUFFIXES,
+};
+use crate::meta_tree::{
+    append_backup_keys, check_meta_generation, clear_backup_keys, generate_version, load_meta,
+    open_meta_tree, open_tag_tree, raise_version_floor, read_all_tags, read_tag, write_backup_keys,
+    write_meta, write_tag, GridDbMetadata,
+};
+use crate::read_cache::ReadCache;
+use crate::version_change_tree::{
+    archive_version, compose_archived_changes, encode_archived_changes, open_version_change_tree,
+    remove_archived_version, serialize_version_changes, VersionChanges,
+};
+use crate::version_graph_tree::{
+    compute_graph_stats, find_nearest_common_ancestor, find_path_between_versions,
+    get_version_node, is_ancestor_or_self, link_version, open_version_graph_tree,
+    read_all_version_nodes, read_version_node, CommitMeta, GraphStats, VersionNode,
+};
+use crate::working_tree::{
+    compute_reverse_changes, open_working_tree, resolve_stored_value,
+    write_changes_to_working_tree, write_changes_to_working_tree_without_backup, ExtentCursor,
+    ExtentWatcher, WorkingTiles,
+};
+use crate::{tree_name, ArchivedChangeIVec, ArchivedIVec, Level, SmallKeyHashMap, Version};
+
+use ilattice::prelude::Extent;
+use itertools::Itertools;
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{check_archived_root, AlignedVec, Archived, CheckBytes, Deserialize, Infallible};
+use sled::transaction::{
+    abort, ConflictableTransactionResult, TransactionError, TransactionalTree,
+};
+use sled::{IVec, Transactional, Tree};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::Duration;
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AbortReason {
+    /// Failed to find a path from the one parent version to another.
+    NoPathExists,
+    /// Failed to find a path from a version node to the root ancestor. (Missing link).
+    NoPathExistsToRoot,
+    /// Tried to reference [`VersionChanges`] that don't exist in the change tree.
+    MissingVersionChanges,
+    /// Tried to resolve a tag name that doesn't exist.
+    MissingTag,
+    /// Tried to squash a range of versions with merges or other branches (including the working version) attached to its
+    /// interior.
+    SquashNotLinear,
+    /// Tried to insert a version that is already in the version graph.
+    VersionExists,
+    /// Tried to insert bytes that are not a valid archived [`VersionChanges`].
+    InvalidVersionChanges,
+    /// Another handle to the same map changed the metadata since this handle read it. Reopen the map to pick up the other
+    /// handle's changes.
+    MetaConflict,
+    /// Tried to insert archived changes into a content-addressed map. Their values refer to blobs by hash, and the blobs of
+    /// another map can't be resolved or reference counted here.
+    ContentAddressed,
+    /// Tried to cherry-pick a version that the working version descends from. Its archived changes hold the values from
+    /// before its descendants changed them, so applying them would revert those changes instead.
+    AncestorVersion,
+}
+
+/// How a [`GridDb`] retries operations that fail with a transient error. See [`GridDb::set_retry_policy`].
+///
+/// Only two kinds of failures are retried:
+/// - I/O errors of kind [`ErrorKind::Interrupted`](std::io::ErrorKind::Interrupted) or
+///   [`ErrorKind::WouldBlock`](std::io::ErrorKind::WouldBlock) from the storage layer. The transaction is attempted again after
+///   the delay.
+/// - [`AbortReason::MetaConflict`]. The map reloads the metadata and backup keys written by the other handle, then runs the
+///   whole operation again on top of them, without a delay.
+///
+/// sled reruns transactions that conflict with concurrent transactions by itself, so those conflicts never surface as errors.
+/// Every other error, like a full disk or [`AbortReason::NoPathExists`], is returned right away.
+///
+/// The delays block the calling thread. `AsyncGridDb` (with the `tokio` feature) runs operations on the blocking thread pool,
+/// so they don't stall an async executor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The total number of attempts, including the first. 0 is treated like 1.
+    pub max_attempts: u32,
+    /// The delay before the first retry. It doubles for every retry after that.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// A single attempt.
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Calls `attempt` until it succeeds, fails with an error that isn't a transient I/O error, or runs out of attempts.
+    /// Returns the last result.
+    fn run<T, E>(
+        &self,
+        mut attempt: impl FnMut() -> Result<T, TransactionError<E>>,
+    ) -> Result<T, TransactionError<E>> {
+        let mut delay = self.base_delay;
+        for _ in 1..self.max_attempts {
+            match attempt() {
+                Err(TransactionError::Storage(sled::Error::Io(e))) if is_transient(&e) => {
+                    log::debug!("Retrying transaction in {:?} after I/O error: {}", delay, e);
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+        attempt()
+    }
+}
+
+/// Whether an I/O error of the storage layer may go away by itself.
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+/// What a call to [`GridDb::write_working_version`] changed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct WriteReport {
+    /// The number of keys written with a value, whether or not they had one before.
+    pub inserted: usize,
+    /// The number of keys written with [`Change::Remove`], whether or not they had a value before.
+    pub removed: usize,
+    /// The number of keys whose old values were backed up, i.e. the keys that the working version hadn't changed before.
+    pub newly_backed_up: usize,
+}
+
+/// What a call to [`GridDb::compact_changes`] rewrote.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CompactionReport {
+    /// The number of archived [`VersionChanges`] that were read.
+    pub entries_scanned: usize,
+    /// The number of archived [`VersionChanges`] replaced by a smaller encoding.
+    pub entries_rewritten: usize,
+    /// The total number of bytes by which the rewritten entries shrank.
+    pub bytes_saved: u64,
+}
+
+/// Entry counts for a [`GridDb`], returned by [`GridDb::stats`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GridDbStats {
+    pub working_entry_count: usize,
+    pub backup_entry_count: usize,
+    pub archived_version_count: usize,
+    /// The number of working version entries at each populated level.
+    pub entries_per_level: BTreeMap<Level, usize>,
+}
+
+/// An inconsistency found by [`GridDb::verify`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IntegrityIssue<K> {
+    /// The key is in the backup key cache, but not in the backup tree.
+    MissingBackupEntry(K),
+    /// The key is in the backup tree, but not in the backup key cache.
+    UncachedBackupEntry(K),
+    /// A version in the graph links to a parent that isn't in the graph.
+    MissingParent { version: Version, parent: Version },
+    /// A version in the graph has no archived changes. Only the parent version of the working version is expected to have
+    /// none.
+    MissingVersionChanges(Version),
+    /// The value stored for the key in the working tree is not a valid archived [`Change`].
+    CorruptWorkingValue(K),
+    /// The value stored for the key in the backup tree is not a valid archived [`Change`].
+    CorruptBackupValue(K),
+    /// The archived changes of the version are not a valid [`VersionChanges`].
+    CorruptArchive(Version),
+}
+
+/// The outcome of [`GridDb::merge`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MergeReport<K> {
+    /// Keys that only the other branch changed, whose values were written to the working version.
+    pub applied: Vec<K>,
+    /// Keys that both branches changed to different values. The working version keeps its own value for each of them.
+    pub conflicts: Vec<MergeConflict<K>>,
+}
+
+/// A key that two merged branches changed differently. `None` means the key has no value on that side.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MergeConflict<K> {
+    pub key: K,
+    pub ours: Option<Change>,
+    pub theirs: Option<Change>,
+}
+
+/// # Quadtree/Octree Database
+///
+/// This database supports CRUD operations on `(DbKey, [u8])` pairs as well as a versioned log of changes.
+///
+/// ## Implementation
+///
+/// All user data is stored in three [`sled::Tree`]s.
+///
+/// ### Working Tree
+///
+/// One tree is used for the *working* [`Version`] of the map, and it stores all of the `[u8]` data for the working
+/// version. All new changes are written to this tree.
+///
+/// ### Backup Tree
+///
+/// As new changes are written, the old values are moved into the "backup tree." The backup tree is just a persistent buffer
+/// that eventually gets archived when the working version is committed.
+///
+/// ### Version Tree
+///
+/// Archived versions get an entry in the "version tree." This stores an actual tree structure where each node has a parent
+/// version (except for the root version). To "revert" to a parent version, all of the backed up values must be re-applied in
+/// reverse order, while the corresponding newer values are archived. By transitivity, any archived version can be reached from
+/// the current working version.
+///
+/// ### Tag Tree
+///
+/// Maps user-provided names to [`Version`]s, so that meaningful save points can be found again later.
+///
+/// ### Blob Tree
+///
+/// Only used by content-addressed maps (see [`GridDbBuilder::content_addressed`]). Each distinct value is stored once in the
+/// blob tree with a reference count, and every other tree holds references to the blobs instead of the values themselves.
+pub struct GridDb<K> {
+    meta_tree: Tree,
+    tag_tree: Tree,
+    working_tree: Tree,
+    backup_tree: Tree,
+    blob_tree: Tree,
+    content_addressed: bool,
+    auto_flush: bool,
+    retry_policy: RetryPolicy,
+    compression: Compression,
+    scratch_capacity: usize,
+
+    // We keep the change tree and graph trees separate so that finding a path between versions does not require reading all of
+    // the changes associated with each version.
+    version_change_tree: Tree,
+    version_graph_tree: Tree,
+
+    /// HACK: We only have this type to work around sled's lack of transactional iteration. When archiving a version, we iterate
+    /// over this set of keys and put the entries into the archive.
+    backup_key_cache: BackupKeyCache<K>,
+    // Zero-copy isn't super important for this tiny struct, so we just copy it for convenience.
+    cached_meta: GridDbMetadata,
+    /// Deserialized working version values, if enabled with [`GridDbBuilder::read_cache`].
+    read_cache: Option<Mutex<ReadCache<K>>>,
+}
+
+/// Options for opening a [`GridDb<K>`]. Each option is set with a chainable method, and [`GridDbBuilder::open`] opens the
+/// map.
+#[derive(Clone, Debug)]
+pub struct GridDbBuilder<K> {
+    content_addressed: bool,
+    auto_flush: bool,
+    read_cache_capacity: usize,
+    retry_policy: RetryPolicy,
+    compression: Compression,
+    scratch_capacity: usize,
+    seed: Option<EncodedChanges>,
+    marker: PhantomData<fn() -> K>,
+}
+
+impl<K> Default for GridDbBuilder<K> {
+    fn default() -> Self {
+        Self {
+            content_addressed: false,
+            auto_flush: false,
+            read_cache_capacity: 0,
+            retry_policy: RetryPolicy::default(),
+            compression: Compression::default(),
+            scratch_capacity: 0,
+            seed: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<K> GridDbBuilder<K>
+where
+    K: DbKey,
+    Archived<K>: Deserialize<K, Infallible> + Ord,
+{
+    /// Stores each distinct value only once, in the `{map_name}-blobs` tree, so maps with many identical values take up much
+    /// less space. Blobs are reference counted and removed once nothing refers to them. Reads cost an extra lookup.
+    ///
+    /// APIs that expose the raw working tree values ([`GridDb::watch_extent`], [`GridDb::iter_working_tiles`] and
+    /// [`GridDb::for_each_working_raw`]) see the blob references rather than the values.
+    ///
+    /// A map must always be opened with the same setting.
+    pub fn content_addressed(mut self, content_addressed: bool) -> Self {
+        self.content_addressed = content_addressed;
+        self
+    }
+
+    /// Flushes every tree at the end of [`GridDb::commit_working_version`], so a committed version survives a crash or power
+    /// failure as soon as the commit returns.
+    ///
+    /// Flushing waits for the disk, which makes commits much slower. Without this, committed versions are only durable after
+    /// an explicit [`GridDb::flush`] or sled's periodic background flush.
+    pub fn auto_flush(mut self, auto_flush: bool) -> Self {
+        self.auto_flush = auto_flush;
+        self
+    }
+
+    /// Keeps up to `capacity` deserialized values in memory, evicting the least recently used, so repeated reads through
+    /// [`GridDb::read_working_version_owned`] (and the methods built on it) skip sled and deserialization. Writes invalidate
+    /// the cached values of the keys they change, and branching clears the whole cache.
+    ///
+    /// A capacity of 0 (the default) disables the cache.
+    pub fn read_cache(mut self, capacity: usize) -> Self {
+        self.read_cache_capacity = capacity;
+        self
+    }
+
+    /// See [`GridDb::set_retry_policy`].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Compresses every uncompressed [`Change::Insert`] written to the working version with `compression`, whether it was
+    /// encoded by the caller or by the map itself. Values that are already compressed or checksummed are written as they are,
+    /// and [`GridDb::compact_changes`] compresses the archived values of older versions.
+    ///
+    /// Values that are already stored aren't touched. The setting isn't stored with the map either, so a map can hold values
+    /// compressed in different ways; readers decompress each value according to its own tag.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Preallocates `capacity` bytes of the scratch buffer that the archived changes of each version are serialized into, so
+    /// commits of large working versions don't have to grow it step by step. The default of 0 grows it from scratch.
+    ///
+    /// The space rkyv uses for bookkeeping while serializing is a compile time parameter instead; see
+    /// [`DEFAULT_SCRATCH_SIZE`](crate::DEFAULT_SCRATCH_SIZE).
+    pub fn scratch_capacity(mut self, capacity: usize) -> Self {
+        self.scratch_capacity = capacity;
+        self
+    }
+
+    /// Writes `seed` to the working version and commits it if the map has never been committed. See
+    /// [`GridDb::open_with_seed`].
+    pub fn seed(mut self, seed: EncodedChanges) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Opens the database. On first open, a single working version will be created with no parent version, unless there's a
+    /// [`GridDbBuilder::seed`].
+    pub fn open(
+        self,
+        db: &sled::Db,
+        map_name: &str,
+    ) -> Result<GridDb<K>, TransactionError<AbortReason>> {
+        GridDb::<K>::migrate_legacy_tree_names(db, map_name)?;
+        let (meta_tree, cached_meta) = open_meta_tree(map_name, db)?;
+        let version_change_tree = open_version_change_tree(map_name, db)?;
+        let version_graph_tree = open_version_graph_tree(map_name, db)?;
+        let (backup_tree, backup_key_cache) = open_backup_tree(map_name, db, &meta_tree)?;
+        let working_tree = open_working_tree(map_name, db)?;
+        let tag_tree = open_tag_tree(map_name, db)?;
+        let blob_tree = open_blob_tree(map_name, db)?;
+
+        let mut map = GridDb {
+            meta_tree,
+            tag_tree,
+            working_tree,
+            backup_tree,
+            blob_tree,
+            content_addressed: self.content_addressed,
+            auto_flush: self.auto_flush,
+            retry_policy: self.retry_policy,
+            compression: self.compression,
+            scratch_capacity: self.scratch_capacity,
+            version_change_tree,
+            version_graph_tree,
+            backup_key_cache,
+            cached_meta,
+            read_cache: (self.read_cache_capacity > 0)
+                .then(|| Mutex::new(ReadCache::new(self.read_cache_capacity))),
+        };
+        if let Some(seed) = self.seed {
+            if map.cached_meta.parent_version.is_none() {
+                map.write_working_version(seed)?;
+                map.commit_working_version()?;
+            }
+        }
+        Ok(map)
+    }
+}
+
+impl<K> GridDb<K>
+where
+    K: DbKey,
+    Archived<K>: Deserialize<K, Infallible> + Ord,
+{
+    /// Opens the database with the default [`GridDbBuilder`] options. On first open, a single working version will be created
+    /// with no parent version.
+    pub fn open(db: &sled::Db, map_name: &str) -> Result<Self, TransactionError<AbortReason>> {
+        GridDbBuilder::default().open(db, map_name)
+    }
+
+    /// Opens the database with a read cache of `cache_capacity` entries. See [`GridDbBuilder::read_cache`].
+    pub fn open_with_cache(
+        db: &sled::Db,
+        map_name: &str,
+        cache_capacity: usize,
+    ) -> Result<Self, TransactionError<AbortReason>> {
+        GridDbBuilder::default()
+            .read_cache(cache_capacity)
+            .open(db, map_name)
+    }
+
+    /// Opens the database like [`GridDb::open`], but if the map has never been committed, `seed` is written to the working
+    /// version and committed right away, so the root version holds the seed. Maps created from the same seed all share the
+    /// same committed base.
+    ///
+    /// If the map already has a committed version, the seed is ignored. That also makes seeding recoverable: should the
+    /// process stop between writing and committing the seed, the next call writes the seed again and commits it. Any other
+    /// uncommitted changes of a never committed map are committed along with the seed.
+    pub fn open_with_seed(
+        db: &sled::Db,
+        map_name: &str,
+        seed: EncodedChanges,
+    ) -> Result<Self, TransactionError<AbortReason>> {
+        GridDbBuilder::default().seed(seed).open(db, map_name)
+    }
+
+    pub fn cached_meta(&self) -> &GridDbMetadata {
+        &self.cached_meta
+    }
+
+    /// Sets how the transactions of this map are retried when they fail with a transient error. By default, every transaction
+    /// is attempted once.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Runs `op` until it doesn't abort with [`AbortReason::MetaConflict`] or runs out of attempts, reloading the metadata
+    /// before every retry. See [`RetryPolicy`].
+    fn retry_on_meta_conflict<T>(
+        &mut self,
+        mut op: impl FnMut(&mut Self) -> Result<T, TransactionError<AbortReason>>,
+    ) -> Result<T, TransactionError<AbortReason>> {
+        for _ in 1..self.retry_policy.max_attempts {
+            match op(self) {
+                Err(TransactionError::Abort(AbortReason::MetaConflict)) => {
+                    log::debug!("Reloading stale metadata of {:?}", self.cached_meta);
+                    self.reload_meta()?;
+                }
+                result => return result,
+            }
+        }
+        op(self)
+    }
+
+    /// Replaces the cached metadata and backup keys with the ones stored by the last handle that changed them.
+    fn reload_meta(&mut self) -> sled::Result<()> {
+        if let Some(meta) = load_meta(&self.meta_tree)? {
+            self.cached_meta = meta;
+        }
+        self.backup_key_cache = load_backup_key_cache(&self.backup_tree, &self.meta_tree)?;
+        if let Some(cache) = &self.read_cache {
+            cache.lock().unwrap().clear();
+        }
+        Ok(())
+    }
+
+    /// An empty [`ChangeEncoder`] that compresses values like this map was configured to with [`GridDbBuilder::compression`].
+    pub fn encoder(&self) -> ChangeEncoder<K> {
+        ChangeEncoder::with_compression(self.compression)
+    }
+
+    fn trees(&self) -> [&Tree; 7] {
+        [
+            &self.meta_tree,
+            &self.tag_tree,
+            &self.working_tree,
+            &self.backup_tree,
+            &self.blob_tree,
+            &self.version_change_tree,
+            &self.version_graph_tree,
+        ]
+    }
+
+    /// Flushes every tree of this map to disk, returning the total number of bytes flushed. See
+    /// [`GridDbBuilder::auto_flush`] to flush after every commit.
+    ///
+    /// When this returns, every write issued before the call is durable on disk, so it can serve as a checkpoint between
+    /// batches. sled only guarantees durability for writes that precede a successful flush; anything after the last flush may
+    /// be lost in a crash. Every operation on this map completes its transaction before returning, so all of them are ordered
+    /// before the flush. All trees share one sled log, which means flushing any tree also persists the others, but we flush
+    /// each of them anyway so the guarantee doesn't rely on that detail.
+    pub fn flush(&self) -> Result<usize, sled::Error> {
+        let mut num_bytes = 0;
+        for tree in self.trees() {
+            num_bytes += tree.flush()?;
+        }
+        Ok(num_bytes)
+    }
+
+    /// Same as [`GridDb::flush`], but doesn't block the thread while waiting for the disk.
+    pub async fn flush_async(&self) -> Result<usize, sled::Error> {
+        let mut num_bytes = 0;
+        for tree in self.trees() {
+            num_bytes += tree.flush_async().await?;
+        }
+        Ok(num_bytes)
+    }
+
+    /// Writes every tree of this map to `writer`, so it can be moved to another [`sled::Db`] with [`GridDb::import`]. This
+    /// includes the uncommitted changes of the working version.
+    ///
+    /// The format is a small header followed by one length-prefixed section per tree, each holding the archived entries of
+    /// that tree.
+    pub fn export<W: Write>(&self, mut writer: W) -> Result<(), ExportError> {
+        let flags = if self.content_addressed {
+            CONTENT_ADDRESSED_FLAG
+        } else {
+            0
+        };
+        write_header(&mut writer, flags, TREE_SUFFIXES.len() as u32)?;
+        for (suffix, tree) in TREE_SUFFIXES.into_iter().zip(self.trees()) {
+            write_section(&mut writer, &ExportSection::from_tree(suffix, tree)?)?;
+        }
+        Ok(())
+    }
+
+    /// Recreates a map exported with [`GridDb::export`] under `map_name` in `db`. All [`Version`]s keep their numbers, so
+    /// versions and tags from the original map can be used with the imported one.
+    ///
+    /// Fails with [`ImportError::MapExists`] if there is already a map called `map_name`.
+    pub fn import<R: Read>(
+        db: &sled::Db,
+        map_name: &str,
+        mut reader: R,
+    ) -> Result<Self, ImportError> {
+        let (flags, num_sections) = read_header(&mut reader)?;
+        let mut sections = Vec::new();
+        for _ in 0..num_sections {
+            sections.push(read_section(&mut reader)?);
+        }
+
+        Self::migrate_legacy_tree_names(db, map_name)?;
+        let trees = TREE_SUFFIXES
+            .into_iter()
+            .map(|suffix| db.open_tree(tree_name(map_name, suffix)))
+            .collect::<sled::Result<Vec<_>>>()?;
+        if trees.iter().any(|tree| !tree.is_empty()) {
+            return Err(ImportError::MapExists);
+        }
+        let mut batches = vec![sled::Batch::default(); trees.len()];
+        for section in sections.into_iter() {
+            let tree_index = TREE_SUFFIXES
+                .iter()
+                .position(|&suffix| suffix == section.name)
+                .ok_or(ImportError::InvalidFormat("unknown tree"))?;
+            for (key, value) in section.entries.into_iter() {
+                batches[tree_index].insert(key, value);
+            }
+        }
+        trees
+            .as_slice()
+            .transaction(|txns| {
+                for (txn, batch) in txns.iter().zip(batches.iter()) {
+                    txn.apply_batch(batch)?;
+                }
+                Ok(())
+            })
+            .map_err(with_abort_reason)?;
+
+        let map = GridDbBuilder::default()
+            .content_addressed(flags & CONTENT_ADDRESSED_FLAG != 0)
+            .open(db, map_name)?;
+
+        // Every version of the map was the working version at some point, so the working version has the largest number. New
+        // versions must not collide with the imported ones.
+        let working_version = map.cached_meta.working_version;
+        map.meta_tree
+            .transaction(|meta_txn| {
+                raise_version_floor(meta_txn, working_version)?;
+                Ok(())
+            })
+            .map_err(with_abort_reason)?;
+
+        Ok(map)
+    }
+
+    /// Moves the trees of the map called `map_name` from the names they had before dashes in map names were escaped, returning
+    /// the number of trees moved. Maps without dashes in their names never need this.
+    ///
+    /// [`GridDbBuilder::open`] and [`GridDb::import`] run this first, so an existing map is never mistaken for a new one. The
+    /// entries of each old tree are copied into the new tree before the old tree is dropped, so if this is interrupted, it's
+    /// safe to run again.
+    pub fn migrate_legacy_tree_names(db: &sled::Db, map_name: &str) -> sled::Result<usize> {
+        if !map_name.contains('-') {
+            return Ok(0);
+        }
+        let existing = db.tree_names();
+        let mut num_moved = 0;
+        for suffix in TREE_SUFFIXES {
+            let legacy_name = format!("{}-{}", map_name, suffix);
+            let name = tree_name(map_name, suffix);
+            if legacy_name == name || !existing.iter().any(|n| n == legacy_name.as_bytes()) {
+                continue;
+            }
+            let legacy_tree = db.open_tree(&legacy_name)?;
+            let mut batch = sled::Batch::default();
+            for iter_result in legacy_tree.iter() {
+                let (key, value) = iter_result?;
+                batch.insert(key, value);
+            }
+            db.open_tree(name)?.apply_batch(batch)?;
+            db.drop_tree(legacy_name)?;
+            num_moved += 1;
+        }
+        Ok(num_moved)
+    }
+
+    /// Writes `changes` to the working version and stores the old values in the backup tree. Uncompressed inserts are
+    /// compressed first, if the map was opened with [`GridDbBuilder::compression`].
+    ///
+    /// Aborts with [`AbortReason::MetaConflict`] if another handle to the same map committed or branched since this handle
+    /// read the metadata, like every other write.
+    pub fn write_working_version(
+        &mut self,
+        changes: EncodedChanges,
+    ) -> Result<WriteReport, TransactionError<AbortReason>> {
+        let changes = self.compress_inserts(changes);
+        self.write_changes(changes, true)
+    }
+
+    /// Compresses the uncompressed inserts of `changes` with [`GridDbBuilder::compression`].
+    fn compress_inserts(&self, mut changes: EncodedChanges) -> EncodedChanges {
+        if self.compression == Compression::None {
+            return changes;
+        }
+        for (_, change) in changes.changes.iter_mut() {
+            if let ArchivedChange::Insert(_) = change.as_ref() {
+                let compressed = change.deserialize().compress(self.compression);
+                *change = unsafe { ArchivedIVec::new(IVec::from(compressed.serialize().as_ref())) };
+            }
+        }
+        changes
+    }
+
+    /// Same as [`GridDb::write_working_version`], but serializes `changes` without a [`ChangeEncoder`]. For bulk loads that
+    /// already produce changes in sled key order (by level, then Morton order), this skips the encoder's hash map and sort.
+    /// Values are compressed like [`GridDb::encoder`] would.
+    ///
+    /// The keys must be strictly increasing, so there can't be more than one change per key. Debug builds panic otherwise;
+    /// release builds trust the caller, and out of order keys are written in the order given.
+    pub fn write_presorted(
+        &mut self,
+        changes: impl Iterator<Item = (K, Change)>,
+    ) -> Result<WriteReport, TransactionError<AbortReason>> {
+        let mut encoded: Vec<(IVec, ArchivedChangeIVec)> =
+            Vec::with_capacity(changes.size_hint().0);
+        for (key, change) in changes {
+            let key_bytes = IVec::from(key.as_sled_key().as_ref());
+            if let Some((prev_key_bytes, _)) = encoded.last() {
+                debug_assert!(
+                    *prev_key_bytes < key_bytes,
+                    "write_presorted: {:?} follows {:?}; keys must be strictly increasing",
+                    key,
+                    K::from_sled_key(prev_key_bytes)
+                );
+            }
+            let change = change.compress(self.compression);
+            encoded.push((key_bytes, unsafe {
+                ArchivedIVec::new(IVec::from(change.serialize().as_ref()))
+            }));
+        }
+        self.write_working_version(EncodedChanges { changes: encoded })
+    }
+
+    /// If `store_blobs` is `false` on a content-addressed map, then the values of `changes` must already be blob references.
+    fn write_changes(
+        &mut self,
+        changes: EncodedChanges,
+        store_blobs: bool,
+    ) -> Result<WriteReport, TransactionError<AbortReason>> {
+        self.retry_on_meta_conflict(|map| map.try_write_changes(&changes, store_blobs))
+    }
+
+    /// Like [`GridDb::write_changes`], but without retrying after an [`AbortReason::MetaConflict`].
+    fn try_write_changes(
+        &mut self,
+        changes: &EncodedChanges,
+        store_blobs: bool,
+    ) -> Result<WriteReport, TransactionError<AbortReason>> {
+        log::trace!("Writing to {:?}", self.cached_meta.working_version);
+        let new_backup_keys = self.retry_policy.run(|| {
+            (
+                &self.working_tree,
+                &self.backup_tree,
+                &self.meta_tree,
+                &self.blob_tree,
+            )
+                .transaction(|(working_txn, backup_txn, meta_txn, blob_txn)| {
+                    self.write_changes_in_txn(
+                        [working_txn, backup_txn, meta_txn, blob_txn],
+                        changes.clone(),
+                        store_blobs,
+                    )
+                })
+        })?;
+        let removed = changes
+            .changes
+            .iter()
+            .filter(|(_, change)| matches!(change.as_ref(), ArchivedChange::Remove))
+            .count();
+        let report = WriteReport {
+            inserted: changes.changes.len() - removed,
+            removed,
+            newly_backed_up: new_backup_keys.len(),
+        };
+        self.insert_backup_keys(new_backup_keys);
+        self.invalidate_cached(changes);
+        Ok(report)
+    }
+
+    /// Writes `changes` to the working, backup, meta and blob trees (in that order), returning the keys that were newly
+    /// backed up. They must be added to the backup key cache once the transaction succeeds.
+    fn write_changes_in_txn(
+        &self,
+        [working_txn, backup_txn, meta_txn, blob_txn]: [&TransactionalTree; 4],
+        changes: EncodedChanges,
+        store_blobs: bool,
+    ) -> ConflictableTransactionResult<Vec<K>, AbortReason> {
+        check_meta_generation(meta_txn, &self.cached_meta)?;
+        let changes = if self.content_addressed {
+            reference_blobs(
+                working_txn,
+                blob_txn,
+                Some(&self.backup_key_cache),
+                changes,
+                store_blobs,
+            )?
+        } else {
+            changes
+        };
+        let reverse_changes =
+            write_changes_to_working_tree(working_txn, &self.backup_key_cache, changes)?;
+        append_backup_keys(meta_txn, reverse_changes.changes.iter().map(|(key, _)| key))?;
+        let new_backup_keys = reverse_changes
+            .changes
+            .iter()
+            .map(|(key, _)| K::from_sled_key(key))
+            .collect();
+        write_changes_to_backup_tree(backup_txn, reverse_changes)?;
+        Ok(new_backup_keys)
+    }
+
+    /// Drops the cached values of the keys written by `changes`.
+    fn invalidate_cached(&self, changes: &EncodedChanges) {
+        if let Some(cache) = &self.read_cache {
+            let mut cache = cache.lock().unwrap();
+            for (key_bytes, _) in changes.changes.iter() {
+                cache.invalidate(&K::from_sled_key(key_bytes));
+            }
+        }
+    }
+
+    fn insert_backup_keys(&mut self, new_backup_keys: Vec<K>) {
+        for key in new_backup_keys.into_iter() {
+            debug_assert!(!self.backup_key_cache.keys.contains(&key));
+            self.backup_key_cache.keys.insert(key);
+        }
+    }
+
+    /// Computes the [`VersionChanges`] that [`GridDb::write_working_version`] would add to the backup for `changes`, without
+    /// writing anything.
+    ///
+    /// Like the backup itself, this only holds the oldest value of each key, so keys that were already changed in the working
+    /// version are left out. In a content-addressed map, the values are resolved from their blobs.
+    pub fn preview_working_version(
+        &self,
+        changes: &EncodedChanges,
+    ) -> Result<VersionChanges<K>, sled::Error> {
+        let mut incoming = Vec::with_capacity(changes.changes.len());
+        let mut current = BTreeMap::new();
+        for (key_bytes, change) in changes.changes.iter() {
+            let key = K::from_sled_key(key_bytes);
+            if self.backup_key_cache.keys.contains(&key) {
+                continue;
+            }
+            if let Some(value) = self.read_working_version(key.clone())? {
+                current.insert(key, value.deserialize());
+            }
+            incoming.push((key_bytes.clone(), change.clone()));
+        }
+        let reverse_changes =
+            compute_reverse_changes(&current, &EncodedChanges { changes: incoming });
+        Ok(VersionChanges::from(&reverse_changes))
+    }
+
+    /// Writes `changes` to the working version *without* storing the old values in the backup tree. This is cheaper than
+    /// [`GridDb::write_working_version`], e.g. when importing a known-good state.
+    ///
+    /// **These changes can't be reverted by the version system.** The overwritten values are lost, and since the changed keys
+    /// aren't recorded in the committed [`VersionChanges`], the new values will remain in place when branching to any other
+    /// version (unless a later, backed-up write changes the same keys).
+    pub fn write_working_version_no_backup(
+        &mut self,
+        changes: EncodedChanges,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        let changes = self.compress_inserts(changes);
+        log::trace!(
+            "Writing to {:?} without backup",
+            self.cached_meta.working_version
+        );
+        self.retry_on_meta_conflict(|map| {
+            map.retry_policy.run(|| {
+                (&map.working_tree, &map.meta_tree, &map.blob_tree).transaction(
+                    |(working_txn, meta_txn, blob_txn)| {
+                        check_meta_generation(meta_txn, &map.cached_meta)?;
+                        let changes = if map.content_addressed {
+                            reference_blobs::<K>(
+                                working_txn,
+                                blob_txn,
+                                None,
+                                changes.clone(),
+                                true,
+                            )?
+                        } else {
+                            changes.clone()
+                        };
+                        write_changes_to_working_tree_without_backup(working_txn, changes)?;
+                        Ok(())
+                    },
+                )
+            })
+        })?;
+        self.invalidate_cached(&changes);
+        Ok(())
+    }
+
+    /// Removes every entry of the working version at `level` inside of `extent`, returning the number of entries removed.
+    ///
+    /// The old values are backed up like any other write, so the removal can be reverted.
+    pub fn remove_extent(
+        &mut self,
+        level: Level,
+        extent: Extent<K::Coords>,
+    ) -> Result<usize, TransactionError<AbortReason>> {
+        let mut encoder = ChangeEncoder::default();
+        let mut num_removed = 0;
+        for iter_result in self.iter_working_extent(level, extent) {
+            let (key, _) = iter_result?;
+            encoder.add_change(key, Change::Remove);
+            num_removed += 1;
+        }
+        if num_removed > 0 {
+            self.write_working_version(encoder.encode())?;
+        }
+        Ok(num_removed)
+    }
+
+    /// Removes the node at `key` along with all of its descendants down to `down_to_level`, returning the number of entries
+    /// removed.
+    ///
+    /// The descendants of a node at any level occupy one contiguous Morton range, so each level costs a single range scan. The
+    /// old values are backed up like any other write, so the removal can be reverted.
+    pub fn remove_subtree(
+        &mut self,
+        key: K,
+        down_to_level: Level,
+    ) -> Result<usize, TransactionError<AbortReason>> {
+        let mut encoder = ChangeEncoder::default();
+        if self.contains_working_version(key.clone())? {
+            encoder.add_change(key.clone(), Change::Remove);
+        }
+        let (mut first, mut last) = (key.clone(), key);
+        while first.level() > down_to_level {
+            // Children are in Morton order, so the first and last descendants bound the range.
+            match (first.children().first(), last.children().last()) {
+                (Some(first_child), Some(last_child)) => {
+                    first = first_child.clone();
+                    last = last_child.clone();
+                }
+                _ => break,
+            }
+            for iter_result in self
+                .working_tree
+                .range(first.as_sled_key()..=last.as_sled_key())
+            {
+                let (key_bytes, _) = iter_result?;
+                encoder.add_change(K::from_sled_key(&key_bytes), Change::Remove);
+            }
+        }
+        let num_removed = encoder.len();
+        if num_removed > 0 {
+            self.write_working_version(encoder.encode())?;
+        }
+        Ok(num_removed)
+    }
+
+    /// Copies every entry of `source`'s working version at `level` inside of `extent` into the working version of this map,
+    /// returning the number of keys written. If `replace` is `true`, entries of this map inside of `extent` that are absent
+    /// from `source` are removed, so the region ends up identical to `source`'s.
+    ///
+    /// The old values are backed up like any other write, so the copy can be reverted.
+    pub fn copy_extent_from(
+        &mut self,
+        source: &GridDb<K>,
+        level: Level,
+        extent: Extent<K::Coords>,
+        replace: bool,
+    ) -> Result<usize, TransactionError<AbortReason>> {
+        let mut encoder = ChangeEncoder::default();
+        let mut num_written = 0;
+        for iter_result in source.iter_working_extent(level, extent.clone()) {
+            let (key, value) = iter_result?;
+            let value = resolve_stored_value(source.blobs(), unsafe { ArchivedIVec::new(value) })?;
+            // Compressed payloads are copied as-is.
+            encoder.add_change(key, value.deserialize());
+            num_written += 1;
+        }
+        if replace {
+            for iter_result in self.iter_working_extent(level, extent) {
+                let (key, _) = iter_result?;
+                if !source.contains_working_version(key.clone())? {
+                    encoder.add_change(key, Change::Remove);
+                    num_written += 1;
+                }
+            }
+        }
+        if num_written > 0 {
+            self.write_working_version(encoder.encode())?;
+        }
+        Ok(num_written)
+    }
+
+    /// Reads the (decompressed) payloads of every entry of the working version at `level` inside of `extent`, in Morton order.
+    /// Removes are left out, should the working tree ever hold any.
+    pub fn snapshot_extent(
+        &self,
+        level: Level,
+        extent: Extent<K::Coords>,
+    ) -> Result<BTreeMap<K, Box<[u8]>>, sled::Error> {
+        let mut snapshot = BTreeMap::new();
+        for iter_result in self.iter_working_extent(level, extent) {
+            let (key, value) = iter_result?;
+            let stored = unsafe { ArchivedChangeIVec::new(value) };
+            if matches!(stored.as_ref(), ArchivedChange::Remove) {
+                continue;
+            }
+            let value = resolve_stored_value(self.blobs(), stored)?;
+            if let Change::Insert(payload) = value.deserialize().decompress()? {
+                snapshot.insert(key, payload);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Like [`GridDb::snapshot_extent`], but reads the entries in batches of whatever size the caller asks for, so a large
+    /// extent can be loaded a piece at a time.
+    pub fn extent_cursor(&self, level: Level, extent: Extent<K::Coords>) -> ExtentCursor<K> {
+        ExtentCursor::new(&self.working_tree, self.blobs(), level, extent)
+    }
+
+    /// The tree that working tree values refer to, if this map is content-addressed.
+    fn blobs(&self) -> Option<&Tree> {
+        self.content_addressed.then(|| &self.blob_tree)
+    }
+
+    /// Scans the Morton range of `extent` in the working tree, skipping the keys that fall outside of `extent`.
+    fn iter_working_extent(
+        &self,
+        level: Level,
+        extent: Extent<K::Coords>,
+    ) -> impl Iterator<Item = Result<(K, IVec), sled::Error>> {
+        let range = K::extent_range(level, extent.clone());
+        self.working_tree
+            .range(range.start().as_sled_key()..=range.end().as_sled_key())
+            .filter_map(move |iter_result| match iter_result {
+                Ok((key_bytes, value)) => {
+                    let key = K::from_sled_key(&key_bytes);
+                    K::extent_contains(&extent, key.coords()).then(|| Ok((key, value)))
+                }
+                Err(e) => Some(Err(e)),
+            })
+    }
+
+    /// Reads the compressed bytes of the chunk at `key` for the working version.
+    ///
+    /// Fails with a [`ChecksumMismatch`](crate::ChecksumMismatch) (wrapped in [`sled::Error::Io`]) if the value was written
+    /// with a checksum that it no longer matches.
+    pub fn read_working_version(&self, key: K) -> Result<Option<ArchivedChangeIVec>, sled::Error> {
+        let bytes = self
+            .working_tree
+            .get(IVec::from(key.as_sled_key().as_ref()))?;
+        bytes
+            .map(|b| resolve_stored_value(self.blobs(), unsafe { ArchivedIVec::new(b) }))
+            .transpose()
+    }
+
+    /// Calls `f` with the archived [`Change`] stored at `key` for the working version, borrowed straight from the bytes sled
+    /// returned, or returns `None` if there is no value. Nothing is copied or deserialized, so this is the cheapest way to
+    /// inspect a few bytes of a value.
+    pub fn with_working_value<R>(
+        &self,
+        key: K,
+        f: impl FnOnce(&ArchivedChange) -> R,
+    ) -> Result<Option<R>, sled::Error> {
+        Ok(self
+            .read_working_version(key)?
+            .map(|value| f(value.as_ref())))
+    }
+
+    /// Returns `true` if there is a value at `key` in the working version. The value is neither copied nor deserialized.
+    pub fn contains_working_version(&self, key: K) -> Result<bool, sled::Error> {
+        self.working_tree.contains_key(key.as_sled_key().as_ref())
+    }
+
+    /// The length in bytes of the archived [`Change`] stored at `key` for the working version, or `None` if there is no value.
+    ///
+    /// sled has no size-only lookup, so the value is fetched, but it isn't copied or deserialized.
+    pub fn working_value_size(&self, key: K) -> Result<Option<usize>, sled::Error> {
+        if self.content_addressed {
+            return Ok(self
+                .read_working_version(key)?
+                .map(|value| value.as_bytes().len()));
+        }
+        Ok(self
+            .working_tree
+            .get(key.as_sled_key().as_ref())?
+            .map(|bytes| bytes.len()))
+    }
+
+    /// Subscribes to writes on the working version at `level` inside of `extent`.
+    ///
+    /// The returned iterator blocks until the next matching write lands, so it's usually driven from another thread.
+    pub fn watch_extent(&self, level: Level, extent: Extent<K::Coords>) -> ExtentWatcher<K> {
+        ExtentWatcher::new(&self.working_tree, level, extent)
+    }
+
+    /// Partitions the working version into tiles at `tile_level`, yielding each non-empty tile's key along with the entries it
+    /// contains.
+    ///
+    /// Tiles are yielded in Morton order, each exactly once. A tile's entries are ordered by level, then Morton code. Entries
+    /// above `tile_level` are skipped.
+    ///
+    /// sled orders keys by level before Morton code, so this streams one range scan per level up to `tile_level` and merges
+    /// them by tile, holding only one entry per level in between.
+    pub fn iter_working_tiles(&self, tile_level: Level) -> WorkingTiles<K> {
+        WorkingTiles::new(&self.working_tree, tile_level)
+    }
+
+    /// The number of entries in the working version.
+    pub fn working_len(&self) -> usize {
+        self.working_tree.len()
+    }
+
+    /// Passes the raw sled key and value bytes of every working version entry to `f`, in key order.
+    ///
+    /// Nothing is decoded, so scan-heavy callers only pay for [`DbKey::from_sled_key`] and [`ArchivedChangeIVec`] when they
+    /// need them.
+    pub fn for_each_working_raw(&self, mut f: impl FnMut(&[u8], &[u8])) -> Result<(), sled::Error> {
+        for iter_result in self.working_tree.iter() {
+            let (key_bytes, value) = iter_result?;
+            f(&key_bytes, &value);
+        }
+        Ok(())
+    }
+
+    /// Counts the entries in each tree. Only the level byte of each working key is read; no keys or values are decoded.
+    pub fn stats(&self) -> Result<GridDbStats, sled::Error> {
+        let mut entries_per_level = BTreeMap::new();
+        for iter_result in self.working_tree.iter().keys() {
+            // The first byte of every key is the level.
+            *entries_per_level.entry(iter_result?[0]).or_insert(0) += 1;
+        }
+        Ok(GridDbStats {
+            working_entry_count: entries_per_level.values().sum(),
+            backup_entry_count: self.backup_tree.len(),
+            archived_version_count: self.version_change_tree.len(),
+            entries_per_level,
+        })
+    }
+
+    /// Computes a digest of the working version's contents for each populated level.
+    ///
+    /// The hashes are stable across runs and platforms, so they can be compared between syncs to detect which levels changed.
+    pub fn level_content_hashes(&self) -> Result<BTreeMap<Level, u64>, sled::Error> {
+        let mut hashers: BTreeMap<Level, Xxh3> = BTreeMap::new();
+        for iter_result in self.working_tree.iter() {
+            let (key_bytes, value) = iter_result?;
+            // The first byte of every key is the level.
+            let hasher = hashers.entry(key_bytes[0]).or_insert_with(Xxh3::new);
+            hasher.update(&key_bytes);
+            hasher.update(&(value.len() as u64).to_le_bytes());
+            hasher.update(&value);
+        }
+        Ok(hashers
+            .into_iter()
+            .map(|(level, hasher)| (level, hasher.digest()))
+            .collect())
+    }
+
+    /// Scans the version graph to summarize its shape. This doesn't read any of the archived changes.
+    pub fn graph_stats(&self) -> Result<GraphStats, sled::Error> {
+        compute_graph_stats(&self.version_graph_tree)
+    }
+
+    /// Groups the keys of the working version that hold identical values, returning only the groups with more than one key.
+    ///
+    /// Each group is identified by the hash of the stored bytes. Values with colliding hashes are compared byte-for-byte, so
+    /// all keys in a group really do share the same value.
+    pub fn duplicate_value_groups(&self) -> Result<Vec<(u64, Vec<K>)>, sled::Error> {
+        let mut buckets: SmallKeyHashMap<u64, Vec<(IVec, Vec<K>)>> = Default::default();
+        for iter_result in self.working_tree.iter() {
+            let (key_bytes, value) = iter_result?;
+            let key = K::from_sled_key(&key_bytes);
+            let bucket = buckets.entry(xxh3_64(&value)).or_default();
+            if let Some((_, keys)) = bucket.iter_mut().find(|(v, _)| *v == value) {
+                keys.push(key);
+            } else {
+                bucket.push((value, vec![key]));
+            }
+        }
+        let mut groups: Vec<_> = buckets
+            .into_iter()
+            .flat_map(|(hash, bucket)| bucket.into_iter().map(move |(_, keys)| (hash, keys)))
+            .filter(|(_, keys)| keys.len() > 1)
+            .collect();
+        groups.sort_by(|(_, keys1), (_, keys2)| keys1[0].cmp(&keys2[0]));
+        Ok(groups)
+    }
+
+    /// Like [`GridDb::read_working_version`], but deserializes (and decompresses) the [`Change`].
+    ///
+    /// This is the read served by the read cache, if there is one (see [`GridDbBuilder::read_cache`]).
+    pub fn read_working_version_owned(&self, key: K) -> Result<Option<Change>, sled::Error> {
+        if let Some(cache) = &self.read_cache {
+            if let Some(value) = cache.lock().unwrap().get(&key) {
+                return Ok(value);
+            }
+        }
+        let value = self
+            .read_working_version(key.clone())?
+            .map(|c| c.deserialize().decompress())
+            .transpose()?;
+        if let Some(cache) = &self.read_cache {
+            cache.lock().unwrap().insert(key, value.clone());
+        }
+        Ok(value)
+    }
+
+    /// Reads the working version values of all `keys`, like [`GridDb::read_working_version_owned`], returning them in the same
+    /// order as `keys`.
+    ///
+    /// The keys are read in the order of their sled keys, so neighboring pages of the tree are touched one after another
+    /// instead of at random.
+    pub fn read_working_many(&self, keys: &[K]) -> Result<Vec<(K, Option<Change>)>, sled::Error> {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by_cached_key(|&i| keys[i].as_sled_key().as_ref().to_vec());
+        let mut values = vec![None; keys.len()];
+        for i in order {
+            values[i] = self.read_working_version_owned(keys[i].clone())?;
+        }
+        Ok(keys.iter().cloned().zip(values).collect())
+    }
+
+    /// Same as [`GridDb::read_working_version_owned`] for the key of the node at `coords` on `level` (see
+    /// [`DbKey::from_coords`]).
+    pub fn read_at_coords(
+        &self,
+        level: Level,
+        coords: K::Coords,
+    ) -> Result<Option<Change>, sled::Error> {
+        self.read_working_version_owned(K::from_coords(level, coords))
+    }
+
+    /// Reads the working version values of the face neighbors of `key` (see [`DbKey::face_neighbors`]), with `None` for
+    /// absent neighbors.
+    pub fn read_neighbors(&self, key: K) -> Result<Vec<(K, Option<Change>)>, sled::Error> {
+        key.face_neighbors()
+            .into_iter()
+            .map(|neighbor| {
+                let value = self.read_working_version_owned(neighbor.clone())?;
+                Ok((neighbor, value))
+            })
+            .collect()
+    }
+
+    /// Reads the working version values of the children of `parent` (see [`DbKey::children`]), with `None` for absent
+    /// children.
+    pub fn read_children(&self, parent: K) -> Result<Vec<(K, Option<Change>)>, sled::Error> {
+        parent
+            .children()
+            .into_iter()
+            .map(|child| {
+                let value = self.read_working_version_owned(child.clone())?;
+                Ok((child, value))
+            })
+            .collect()
+    }
+
+    /// Regenerates the value of `parent` from its children, as when a coarser level of detail is derived from a finer one.
+    ///
+    /// `reduce` is given the result of [`GridDb::read_children`]. Its output is written to `parent` like any other write, or
+    /// removes `parent` if it's `None`.
+    pub fn write_downsampled(
+        &mut self,
+        parent: K,
+        reduce: impl FnOnce(&[(K, Option<Change>)]) -> Option<Box<[u8]>>,
+    ) -> Result<WriteReport, TransactionError<AbortReason>> {
+        let children = self.read_children(parent.clone())?;
+        let change = match reduce(&children) {
+            Some(value) => Change::Insert(value),
+            None => Change::Remove,
+        };
+        let mut encoder = self.encoder();
+        encoder.add_change(parent, change);
+        self.write_working_version(encoder.encode())
+    }
+
+    /// Reads the (decompressed) payload at `key` for the working version, or a copy of `default` if there is no value.
+    pub fn read_or_default(&self, key: K, default: &[u8]) -> Result<Box<[u8]>, sled::Error> {
+        Ok(match self.read_working_version_owned(key)? {
+            Some(Change::Insert(data)) => data,
+            // Removes should never be written to the working tree, but the default is still the right answer.
+            _ => default.into(),
+        })
+    }
+
+    /// Archives the backup tree entries into a [`VersionChanges`] that gets serialized and stored in the version change tree
+    /// with the current working [`Version`]. A new working version is generated and the old working version becomes the parent
+    /// version.
+    ///
+    /// Returns the committed version, which is now the parent version. Nothing happens and `None` is returned if the working
+    /// version has no changes.
+    ///
+    /// The version is recorded with [`CommitMeta::now`]. Use [`GridDb::commit_working_version_with`] to provide other
+    /// metadata.
+    pub fn commit_working_version(
+        &mut self,
+    ) -> Result<Option<Version>, TransactionError<AbortReason>> {
+        self.commit_working_version_with(CommitMeta::now())
+    }
+
+    /// Same as [`GridDb::commit_working_version`], but records the provided `meta` with the committed version.
+    pub fn commit_working_version_with(
+        &mut self,
+        meta: CommitMeta,
+    ) -> Result<Option<Version>, TransactionError<AbortReason>> {
+        self.retry_on_meta_conflict(|map| map.try_commit_working_version(&meta))
+    }
+
+    /// Like [`GridDb::commit_working_version_with`], but without retrying after an [`AbortReason::MetaConflict`].
+    fn try_commit_working_version(
+        &mut self,
+        meta: &CommitMeta,
+    ) -> Result<Option<Version>, TransactionError<AbortReason>> {
+        if self.backup_key_cache.keys.is_empty() {
+            return Ok(None);
+        }
+
+        log::trace!(
+            "Committing non-empty {:?}",
+            self.cached_meta.working_version
+        );
+
+        let new_meta = self.retry_policy.run(|| {
+            (
+                &self.backup_tree,
+                &self.version_graph_tree,
+                &self.version_change_tree,
+                &self.meta_tree,
+                &self.blob_tree,
+            )
+                .transaction(
+                    |(backup_txn, graph_txn, changes_txn, meta_txn, blob_txn)| {
+                        check_meta_generation(meta_txn, &self.cached_meta)?;
+                        if let Some(parent) = self.cached_meta.parent_version {
+                            log::trace!("Archiving {:?} from backup", parent);
+                            archive_version(
+                                changes_txn,
+                                parent,
+                                &commit_backup(backup_txn, &self.backup_key_cache)?,
+                                self.scratch_capacity,
+                            )?;
+                        } else {
+                            if self.content_addressed {
+                                // The backed up values are discarded instead of archived.
+                                for key in self.backup_key_cache.keys.iter() {
+                                    if let Some(value) =
+                                        backup_txn.get(key.as_sled_key().as_ref())?
+                                    {
+                                        release_dropped_value(blob_txn, value)?;
+                                    }
+                                }
+                            }
+                            // We only need to do this once, but it's important for correctness.
+                            clear_backup(backup_txn, &self.backup_key_cache)?;
+                        }
+                        clear_backup_keys(
+                            meta_txn,
+                            self.backup_key_cache
+                                .keys
+                                .iter()
+                                .map(|key| key.as_sled_key()),
+                        )?;
+                        link_version(
+                            graph_txn,
+                            self.cached_meta.working_version,
+                            VersionNode::with_commit_meta(
+                                self.cached_meta.parent_version,
+                                meta.clone(),
+                            ),
+                        )?;
+                        let new_meta = GridDbMetadata {
+                            grandparent_version: self.cached_meta.parent_version,
+                            parent_version: Some(self.cached_meta.working_version),
+                            working_version: generate_version(meta_txn)?,
+                            meta_generation: self.cached_meta.meta_generation + 1,
+                        };
+                        write_meta(meta_txn, &new_meta)?;
+                        Ok(new_meta)
+                    },
+                )
+        })?;
+        self.backup_key_cache.keys.clear();
+        self.cached_meta = new_meta;
+        if self.auto_flush {
+            self.flush()?;
+        }
+        Ok(new_meta.parent_version)
+    }
+
+    /// Applies the archived changes of `version` on top of the working version, without changing the lineage of the working
+    /// version. Keys already changed in the working version are overwritten.
+    ///
+    /// The archived changes of a version are the values it holds for each key that differs from its neighbor in the version
+    /// graph. For the leaf version of another branch, that's exactly the set of changes it committed.
+    ///
+    /// Aborts with [`AbortReason::MissingVersionChanges`] if there are no archived changes for `source`, as is the case for
+    /// the current parent version, and with [`AbortReason::AncestorVersion`] if the working version descends from `source`.
+    pub fn cherry_pick(&mut self, source: Version) -> Result<(), TransactionError<AbortReason>> {
+        let changes = if let Some(bytes) = self.version_change_tree.get(source.into_sled_key())? {
+            unsafe { ArchivedIVec::<VersionChanges<K>>::new(bytes) }
+        } else {
+            return Err(TransactionError::Abort(AbortReason::MissingVersionChanges));
+        };
+        if let Some(parent) = self.cached_meta.parent_version {
+            let nodes = read_all_version_nodes(&self.version_graph_tree)?;
+            if is_ancestor_or_self(&nodes, source, parent) {
+                return Err(TransactionError::Abort(AbortReason::AncestorVersion));
+            }
+        }
+        log::trace!("Cherry-picking {:?}", source);
+        // Archived values are already blob references in a content-addressed map.
+        self.write_changes(encode_archived_changes(changes.as_ref()), false)?;
+        Ok(())
+    }
+
+    /// Merges the branch ending at `other_leaf` into the working version.
+    ///
+    /// Both branches are compared with the nearest common ancestor of the parent version and `other_leaf`. Keys that only the
+    /// other branch changed are written to the working version like any other write, so the merge can be reverted. Keys that
+    /// both branches changed to the same value are left alone, and keys that they changed to different values are reported as
+    /// conflicts, keeping the working version's value. Uncommitted changes count as changes of the current branch.
+    ///
+    /// The other branch is not recorded as a parent of the working version.
+    ///
+    /// Aborts with [`AbortReason::NoPathExists`] if there is no parent version or it shares no ancestor with `other_leaf`.
+    pub fn merge(
+        &mut self,
+        other_leaf: Version,
+    ) -> Result<MergeReport<K>, TransactionError<AbortReason>> {
+        self.retry_on_meta_conflict(|map| map.try_merge(other_leaf))
+    }
+
+    /// Like [`GridDb::merge`], but without retrying after an [`AbortReason::MetaConflict`].
+    fn try_merge(
+        &mut self,
+        other_leaf: Version,
+    ) -> Result<MergeReport<K>, TransactionError<AbortReason>> {
+        let parent = if let Some(parent) = self.cached_meta.parent_version {
+            parent
+        } else {
+            return Err(TransactionError::Abort(AbortReason::NoPathExists));
+        };
+        let (path_to_base, path_to_other) = self.retry_policy.run(|| {
+            self.version_graph_tree.transaction(|graph_txn| {
+                let base = match find_nearest_common_ancestor(graph_txn, parent, other_leaf)? {
+                    Some(base) => base,
+                    None => return abort(AbortReason::NoPathExists),
+                };
+                Ok((
+                    find_path_between_versions(graph_txn, parent, base)?.path,
+                    find_path_between_versions(graph_txn, parent, other_leaf)?.path,
+                ))
+            })
+        })?;
+        log::trace!(
+            "Merging {:?} from base {:?}",
+            other_leaf,
+            path_to_base.last()
+        );
+
+        // The changes that take the parent version to the base and to the other leaf.
+        let to_base = self.compose_path_changes(&path_to_base[1..])?;
+        let to_other = self.compose_path_changes(&path_to_other[1..])?;
+
+        let changed_keys: BTreeSet<&K> = to_base.keys().chain(to_other.keys()).collect();
+        let mut encoder = ChangeEncoder::default();
+        let mut report = MergeReport {
+            applied: Vec::new(),
+            conflicts: Vec::new(),
+        };
+        for key in changed_keys {
+            let parent_value = self.read_parent_value(key)?;
+            let base_value = to_base.get(key).unwrap_or(&parent_value);
+            let their_value = to_other.get(key).unwrap_or(&parent_value);
+            if same_value(their_value, base_value) {
+                continue;
+            }
+            let our_value = self
+                .working_tree
+                .get(key.as_sled_key().as_ref())?
+                .map_or(Change::Remove, |v| {
+                    unsafe { ArchivedChangeIVec::new(v) }.deserialize()
+                });
+            if same_value(&our_value, base_value) {
+                encoder.add_change(key.clone(), their_value.clone());
+                report.applied.push(key.clone());
+            } else if !same_value(&our_value, their_value) {
+                report.conflicts.push(MergeConflict {
+                    key: key.clone(),
+                    ours: self.resolve_value(our_value)?,
+                    theirs: self.resolve_value(their_value.clone())?,
+                });
+            }
+        }
+        if !report.applied.is_empty() {
+            // Archived values are already blob references in a content-addressed map.
+            self.try_write_changes(&encoder.encode(), false)?;
+        }
+        Ok(report)
+    }
+
+    /// Composes the archived changes of every version in `path`. Applying the result to the version before `path` gives the
+    /// last version of `path`.
+    fn compose_path_changes(
+        &self,
+        path: &[Version],
+    ) -> Result<BTreeMap<K, Change>, TransactionError<AbortReason>> {
+        let mut archives = Vec::with_capacity(path.len());
+        for version in path {
+            match self.version_change_tree.get(version.into_sled_key())? {
+                Some(bytes) => {
+                    archives.push(unsafe { ArchivedIVec::<VersionChanges<K>>::new(bytes) })
+                }
+                None => return Err(TransactionError::Abort(AbortReason::MissingVersionChanges)),
+            }
+        }
+        Ok(compose_archived_changes(archives.iter().map(|a| a.as_ref())).changes)
+    }
+
+    /// The stored value of `key` in the parent version, or [`Change::Remove`] if there is none. Keys changed by the working
+    /// version have their parent value in the backup tree.
+    fn read_parent_value(&self, key: &K) -> Result<Change, sled::Error> {
+        let key_bytes = key.as_sled_key();
+        let value = if self.backup_key_cache.keys.contains(key) {
+            // Empty values are compact removes.
+            self.backup_tree
+                .get(key_bytes.as_ref())?
+                .filter(|value| !value.is_empty())
+        } else {
+            self.working_tree.get(key_bytes.as_ref())?
+        };
+        Ok(value.map_or(Change::Remove, |v| {
+            unsafe { ArchivedChangeIVec::new(v) }.deserialize()
+        }))
+    }
+
+    /// Turns a stored value into the (decompressed) value it stands for, or `None` for a [`Change::Remove`].
+    fn resolve_value(&self, stored: Change) -> Result<Option<Change>, sled::Error> {
+        Ok(match stored {
+            Change::Remove => None,
+            Change::Insert(hash) if self.content_addressed => Some(
+                read_blob_by_hash(&self.blob_tree, &hash)?
+                    .deserialize()
+                    .decompress()?,
+            ),
+            other => Some(other.decompress()?),
+        })
+    }
+
+    /// Collapses the versions strictly between `from` and `to` so that `from` becomes the parent of `to`. Returns `to`.
+    ///
+    /// `from` must be reachable from `to` by following only `parent_version` links. The archived changes of the collapsed
+    /// versions are composed into the archive of `from` or `to` (whichever is farther from the current parent version), so
+    /// migrating across the squashed range applies one archive instead of many.
+    ///
+    /// Aborts with [`AbortReason::SquashNotLinear`] if any collapsed version is a merge, is the parent of a version other than
+    /// the next one in the range, or is the parent of the working version.
+    pub fn squash(
+        &mut self,
+        from: Version,
+        to: Version,
+    ) -> Result<Version, TransactionError<AbortReason>> {
+        self.retry_on_meta_conflict(|map| map.try_squash(from, to))
+    }
+
+    /// Like [`GridDb::squash`], but without retrying after an [`AbortReason::MetaConflict`].
+    fn try_squash(
+        &mut self,
+        from: Version,
+        to: Version,
+    ) -> Result<Version, TransactionError<AbortReason>> {
+        let nodes = read_all_version_nodes(&self.version_graph_tree)?;
+
+        let mut range = vec![to];
+        let mut current = to;
+        while current != from {
+            match nodes.get(&current).and_then(|node| node.parent_version) {
+                Some(parent) => {
+                    range.push(parent);
+                    current = parent;
+                }
+                None => return Err(TransactionError::Abort(AbortReason::NoPathExists)),
+            }
+        }
+        range.reverse();
+        if range.len() <= 2 {
+            // Nothing between from and to.
+            return Ok(to);
+        }
+        let interior = &range[1..range.len() - 1];
+
+        // The working version is a child of the parent version, even though it isn't linked into the graph yet.
+        let parent_version = self.cached_meta.parent_version;
+        for (&version, &next) in range.iter().tuple_windows().skip(1) {
+            let has_other_children = parent_version == Some(version)
+                || nodes
+                    .iter()
+                    .any(|(&child, node)| child != next && node.parents().any(|p| p == version));
+            if has_other_children || !nodes[&version].merge_parents.is_empty() {
+                return Err(TransactionError::Abort(AbortReason::SquashNotLinear));
+            }
+        }
+
+        // Every archive holds the values that differ from the neighbor in the direction of the parent version. If the parent
+        // version is on the `to` side of the range, then the archive of `from` absorbs the interior, with changes closer to
+        // `from` taking precedence. Otherwise the archive of `to` absorbs the interior, with changes closer to `to` taking
+        // precedence.
+        let parent_beyond_to = match parent_version {
+            Some(p) => is_ancestor_or_self(&nodes, to, p),
+            None => false,
+        };
+        let (target, composed_versions): (Version, Vec<Version>) = if parent_beyond_to {
+            (
+                from,
+                range[..range.len() - 1].iter().rev().copied().collect(),
+            )
+        } else {
+            (to, range[1..].to_vec())
+        };
+
+        log::trace!("Squashing {:?} into {:?}", interior, target);
+        let new_meta = self.retry_policy.run(|| {
+            (
+                &self.meta_tree,
+                &self.version_graph_tree,
+                &self.version_change_tree,
+                &self.blob_tree,
+            )
+                .transaction(|(meta_txn, graph_txn, change_txn, blob_txn)| {
+                    check_meta_generation(meta_txn, &self.cached_meta)?;
+                    let mut archives = Vec::with_capacity(composed_versions.len());
+                    for &version in composed_versions.iter() {
+                        if let Some(changes) = remove_archived_version::<K>(change_txn, version)? {
+                            archives.push(changes);
+                        } else {
+                            return abort(AbortReason::MissingVersionChanges);
+                        }
+                    }
+                    if self.content_addressed {
+                        // Values that are overwritten by a later archive don't survive the composition.
+                        let mut latest = BTreeMap::new();
+                        for archive in archives.iter() {
+                            for (key, change) in archive.as_ref().changes.iter() {
+                                if let Some(hash) =
+                                    latest.insert(key, change).and_then(blob_ref_hash)
+                                {
+                                    release_blob_ref(blob_txn, hash)?;
+                                }
+                            }
+                        }
+                    }
+                    let composed = compose_archived_changes(archives.iter().map(|a| a.as_ref()));
+                    archive_version(change_txn, target, &composed, self.scratch_capacity)?;
+
+                    for &version in interior {
+                        graph_txn.remove(&version.into_sled_key())?;
+                    }
+                    let mut to_node = if let Some(node) = read_version_node(graph_txn, to)? {
+                        node
+                    } else {
+                        return abort(AbortReason::NoPathExistsToRoot);
+                    };
+                    to_node.parent_version = Some(from);
+                    link_version(graph_txn, to, to_node)?;
+
+                    let mut new_meta = self.cached_meta;
+                    if parent_version == Some(to) {
+                        new_meta.grandparent_version = Some(from);
+                        new_meta.meta_generation += 1;
+                        write_meta(meta_txn, &new_meta)?;
+                    }
+                    Ok(new_meta)
+                })
+        })?;
+        self.cached_meta = new_meta;
+
+        Ok(to)
+    }
+
+    /// Finds the nearest version that both `a` and `b` descend from, following merge parents too. If one of them is an
+    /// ancestor of the other, that's the one returned. Returns `None` if they are in disjoint trees with different roots.
+    ///
+    /// Aborts with [`AbortReason::NoPathExistsToRoot`] if either version isn't in the version graph.
+    pub fn common_ancestor(
+        &self,
+        a: Version,
+        b: Version,
+    ) -> Result<Option<Version>, TransactionError<AbortReason>> {
+        self.retry_policy.run(|| {
+            self.version_graph_tree
+                .transaction(|graph_txn| find_nearest_common_ancestor(graph_txn, a, b))
+        })
+    }
+
+    /// The net number of cells added (positive) or removed (negative) when going from version `a` to version `b`. Both
+    /// versions must be committed.
+    ///
+    /// Only the archived changes between `a` and `b` are read, along with whatever is needed to resolve the keys they touch.
+    pub fn cell_count_delta(
+        &self,
+        a: Version,
+        b: Version,
+    ) -> Result<i64, TransactionError<AbortReason>> {
+        let parent = if let Some(parent) = self.cached_meta.parent_version {
+            parent
+        } else {
+            return Err(TransactionError::Abort(AbortReason::NoPathExists));
+        };
+        let (path_to_a, path_to_b) = self.retry_policy.run(|| {
+            self.version_graph_tree.transaction(|graph_txn| {
+                Ok((
+                    find_path_between_versions(graph_txn, parent, a)?.path,
+                    find_path_between_versions(graph_txn, parent, b)?.path,
+                ))
+            })
+        })?;
+
+        // Archives are relative to the neighbor in the direction of the parent version, so we split the paths where they meet.
+        let meet = path_to_a
+            .iter()
+            .zip(path_to_b.iter())
+            .take_while(|(va, vb)| va == vb)
+            .count()
+            - 1;
+        let a_side = self.read_path_presence(&path_to_a[meet + 1..])?;
+        let b_side = self.read_path_presence(&path_to_b[meet + 1..])?;
+        // The meeting version and its ancestors toward the parent version, which has no archive.
+        let meet_side = self.read_path_presence(&path_to_a[1..=meet])?;
+
+        let changed_keys: BTreeSet<&K> = a_side
+            .iter()
+            .chain(b_side.iter())
+            .flat_map(|presence| presence.keys())
+            .collect();
+        let mut delta = 0;
+        for key in changed_keys {
+            let lookup = |side: &[BTreeMap<K, bool>]| {
+                side.iter().find_map(|presence| presence.get(key).copied())
+            };
+            let at_meet = || -> Result<bool, sled::Error> {
+                match lookup(&meet_side) {
+                    Some(present) => Ok(present),
+                    None => self.parent_version_contains(key),
+                }
+            };
+            let present_at_a = match lookup(&a_side) {
+                Some(present) => present,
+                None => at_meet()?,
+            };
+            let present_at_b = match lookup(&b_side) {
+                Some(present) => present,
+                None => at_meet()?,
+            };
+            delta += present_at_b as i64 - present_at_a as i64;
+        }
+        Ok(delta)
+    }
+
+    /// Reads [`GridDb::read_archived_presence`] for every version in `path`, in reverse order.
+    fn read_path_presence(
+        &self,
+        path: &[Version],
+    ) -> Result<Vec<BTreeMap<K, bool>>, TransactionError<AbortReason>> {
+        path.iter()
+            .rev()
+            .map(|&version| self.read_archived_presence(version))
+            .collect()
+    }
+
+    /// For each key in the archived changes of `version`, whether the key holds a value in `version`.
+    fn read_archived_presence(
+        &self,
+        version: Version,
+    ) -> Result<BTreeMap<K, bool>, TransactionError<AbortReason>> {
+        let bytes = if let Some(bytes) = self.version_change_tree.get(version.into_sled_key())? {
+            bytes
+        } else {
+            return Err(TransactionError::Abort(AbortReason::MissingVersionChanges));
+        };
+        let changes = unsafe { ArchivedIVec::<VersionChanges<K>>::new(bytes) };
+        Ok(changes
+            .as_ref()
+            .changes
+            .iter()
+            .map(|(key, change)| {
+                let key: K = key.deserialize(&mut Infallible).unwrap();
+                (key, !matches!(change, ArchivedChange::Remove))
+            })
+            .collect())
+    }
+
+    /// Whether `key` holds a value in the parent version. Keys changed by the working version have their parent value in the
+    /// backup tree.
+    fn parent_version_contains(&self, key: &K) -> Result<bool, sled::Error> {
+        let key_bytes = key.as_sled_key();
+        if self.backup_key_cache.keys.contains(key) {
+            Ok(match self.backup_tree.get(key_bytes.as_ref())? {
+                Some(value) if !value.is_empty() => {
+                    let value = unsafe { ArchivedChangeIVec::new(value) };
+                    !matches!(value.as_ref(), ArchivedChange::Remove)
+                }
+                // Empty values are compact removes.
+                _ => false,
+            })
+        } else {
+            self.working_tree.contains_key(key_bytes.as_ref())
+        }
+    }
+
+    /// The distinct levels of the keys in the archived changes of `version`. Returns an empty set if `version` has no archived
+    /// changes.
+    ///
+    /// Only the keys of the archive are read; none of the values are deserialized.
+    pub fn version_touched_levels(&self, version: Version) -> Result<BTreeSet<Level>, sled::Error> {
+        let bytes = if let Some(bytes) = self.version_change_tree.get(version.into_sled_key())? {
+            bytes
+        } else {
+            return Ok(BTreeSet::new());
+        };
+        let changes = unsafe { ArchivedIVec::<VersionChanges<K>>::new(bytes) };
+        Ok(changes
+            .as_ref()
+            .changes
+            .keys()
+            .map(|key| {
+                let key: K = key.deserialize(&mut Infallible).unwrap();
+                key.level()
+            })
+            .collect())
+    }
+
+    /// Checks that the backup key cache, backup tree, version graph and version change tree are consistent with each other, and
+    /// that every stored value is valid. All issues found are returned, rather than stopping at the first one.
+    ///
+    /// This reads every tree in full, so it's meant for offline integrity checks.
+    pub fn verify(&self) -> Result<Vec<IntegrityIssue<K>>, sled::Error>
+    where
+        Archived<VersionChanges<K>>: for<'a> CheckBytes<DefaultValidator<'a>>,
+    {
+        let mut issues = Vec::new();
+
+        for key in self.backup_key_cache.keys.iter() {
+            if !self.backup_tree.contains_key(key.as_sled_key().as_ref())? {
+                issues.push(IntegrityIssue::MissingBackupEntry(key.clone()));
+            }
+        }
+        for iter_result in self.backup_tree.iter() {
+            let (key_bytes, value) = iter_result?;
+            let key = K::from_sled_key(&key_bytes);
+            if !self.backup_key_cache.keys.contains(&key) {
+                issues.push(IntegrityIssue::UncachedBackupEntry(key.clone()));
+            }
+            // Empty values are compact removes.
+            if !value.is_empty() && !is_valid_change(&value) {
+                issues.push(IntegrityIssue::CorruptBackupValue(key));
+            }
+        }
+        for iter_result in self.working_tree.iter() {
+            let (key_bytes, value) = iter_result?;
+            if !is_valid_change(&value) {
+                issues.push(IntegrityIssue::CorruptWorkingValue(K::from_sled_key(
+                    &key_bytes,
+                )));
+            }
+        }
+
+        let nodes = read_all_version_nodes(&self.version_graph_tree)?;
+        for (&version, node) in nodes.iter() {
+            for parent in node.parents() {
+                if !nodes.contains_key(&parent) {
+                    issues.push(IntegrityIssue::MissingParent { version, parent });
+                }
+            }
+            if Some(version) != self.cached_meta.parent_version
+                && !self
+                    .version_change_tree
+                    .contains_key(version.into_sled_key())?
+            {
+                issues.push(IntegrityIssue::MissingVersionChanges(version));
+            }
+        }
+        issues.extend(
+            self.validate_all_archives()?
+                .into_iter()
+                .map(IntegrityIssue::CorruptArchive),
+        );
+
+        Ok(issues)
+    }
+
+    /// Replaces the backup key cache (and its persisted copy) with the keys actually found in the backup tree, returning the
+    /// number of keys. This repairs a cache that [`GridDb::verify`] reports out of sync with the backup tree, so that the
+    /// working version can be committed again.
+    ///
+    /// It's always safe to call, but it scans the whole backup tree.
+    pub fn rebuild_backup_cache(&mut self) -> Result<usize, sled::Error> {
+        let cache = scan_backup_keys::<K>(&self.backup_tree)?;
+        write_backup_keys(
+            &self.meta_tree,
+            cache.keys.iter().map(|key| key.as_sled_key()),
+        )?;
+        let num_keys = cache.keys.len();
+        self.backup_key_cache = cache;
+        Ok(num_keys)
+    }
+
+    /// Walks the `parent_version` links from `leaf` down to the root, lazily loading the archived [`VersionChanges`] of one
+    /// version per step. Versions without archived changes, like the current parent version, yield empty changes.
+    ///
+    /// Archives are relative to the current parent version. For every version that descends from it, the archive holds that
+    /// version's values for the keys it changed from its own parent, so after branching from the root, applying the replayed
+    /// changes in reverse (starting from the root's state) reconstructs the state of `leaf`. Versions on the path to the
+    /// current parent version hold the reverse changes instead. In a content-addressed map, the values are blob references.
+    ///
+    /// Aborts with [`AbortReason::NoPathExistsToRoot`] if `leaf` or any of its ancestors is missing from the version graph.
+    #[allow(clippy::type_complexity)]
+    pub fn replay(
+        &self,
+        leaf: Version,
+    ) -> Result<
+        impl Iterator<Item = Result<(Version, VersionChanges<K>), TransactionError<AbortReason>>> + '_,
+        TransactionError<AbortReason>,
+    > {
+        if get_version_node(&self.version_graph_tree, leaf)?.is_none() {
+            return Err(TransactionError::Abort(AbortReason::NoPathExistsToRoot));
+        }
+        let mut next = Some(leaf);
+        Ok(std::iter::from_fn(move || {
+            let version = next.take()?;
+            Some(self.replay_step(version).map(|(parent, changes)| {
+                next = parent;
+                (version, changes)
+            }))
+        }))
+    }
+
+    /// Loads the archived changes and parent of `version` for [`GridDb::replay`].
+    fn replay_step(
+        &self,
+        version: Version,
+    ) -> Result<(Option<Version>, VersionChanges<K>), TransactionError<AbortReason>> {
+        let node = match get_version_node(&self.version_graph_tree, version)? {
+            Some(node) => node,
+            None => return Err(TransactionError::Abort(AbortReason::NoPathExistsToRoot)),
+        };
+        let changes = match self.version_change_tree.get(version.into_sled_key())? {
+            Some(bytes) => unsafe { ArchivedIVec::<VersionChanges<K>>::new(bytes) }.deserialize(),
+            None => VersionChanges::new(BTreeMap::new()),
+        };
+        Ok((node.parent_version, changes))
+    }
+
+    /// The archived [`VersionChanges`] of `version` exactly as they are stored, or `None` if `version` has no archived changes.
+    /// These can be sent elsewhere and inserted with [`GridDb::insert_version_changes_bytes`] without re-serializing.
+    pub fn read_version_changes_bytes(
+        &self,
+        version: Version,
+    ) -> Result<Option<IVec>, sled::Error> {
+        self.version_change_tree.get(version.into_sled_key())
+    }
+
+    /// Inserts `version` as a child of the parent version, with the archived [`VersionChanges`] in `bytes`. The working
+    /// version can then branch from `version` like any other.
+    ///
+    /// Archives are relative to the neighbor in the direction of the parent version, so `bytes` must hold the changes of
+    /// `version` relative to the current parent version. Those are the bytes returned by
+    /// [`GridDb::read_version_changes_bytes`] for a child of the same parent version in another map.
+    ///
+    /// Aborts with [`AbortReason::InvalidVersionChanges`] if `bytes` are malformed or misaligned, with
+    /// [`AbortReason::VersionExists`] if `version` is already in the graph or is the working version, which has no node until
+    /// it's committed, and with [`AbortReason::ContentAddressed`] if this map is content-addressed. Versions this map hasn't
+    /// generated yet are accepted, and the map won't generate them afterwards. An [`AbortReason::MetaConflict`] is never retried, since `bytes` are only valid relative to
+    /// the parent version that the caller saw.
+    pub fn insert_version_changes_bytes(
+        &mut self,
+        version: Version,
+        bytes: &[u8],
+    ) -> Result<(), TransactionError<AbortReason>>
+    where
+        Archived<VersionChanges<K>>: for<'a> CheckBytes<DefaultValidator<'a>>,
+    {
+        if self.content_addressed {
+            return Err(TransactionError::Abort(AbortReason::ContentAddressed));
+        }
+        if check_archived_root::<VersionChanges<K>>(bytes).is_err() {
+            return Err(TransactionError::Abort(AbortReason::InvalidVersionChanges));
+        }
+        let cached_meta = &self.cached_meta;
+        self.retry_policy.run(|| {
+            (
+                &self.meta_tree,
+                &self.version_graph_tree,
+                &self.version_change_tree,
+            )
+                .transaction(|(meta_txn, graph_txn, change_txn)| {
+                    check_meta_generation(meta_txn, cached_meta)?;
+                    if version == cached_meta.working_version
+                        || read_version_node(graph_txn, version)?.is_some()
+                    {
+                        return abort(AbortReason::VersionExists);
+                    }
+                    link_version(
+                        graph_txn,
+                        version,
+                        VersionNode::new(cached_meta.parent_version),
+                    )?;
+                    change_txn.insert(&version.into_sled_key(), bytes)?;
+                    // New versions of this map must not collide with the inserted one.
+                    raise_version_floor(meta_txn, version)?;
+                    Ok(())
+                })
+        })
+    }
+
+    /// Validates the bytes of every archived [`VersionChanges`], returning the versions whose archives are corrupt.
+    ///
+    /// This reads the entire version change tree, so it's meant for offline integrity checks.
+    pub fn validate_all_archives(&self) -> Result<Vec<Version>, sled::Error>
+    where
+        Archived<VersionChanges<K>>: for<'a> CheckBytes<DefaultValidator<'a>>,
+    {
+        let mut invalid = Vec::new();
+        for iter_result in self.version_change_tree.iter() {
+            let (key_bytes, bytes) = iter_result?;
+            // The validator checks alignment, but sled only guarantees 8-byte alignment of values.
+            let mut aligned = AlignedVec::with_capacity(bytes.len());
+            aligned.extend_from_slice(&bytes);
+            if check_archived_root::<VersionChanges<K>>(&aligned).is_err() {
+                invalid.push(Version::from_sled_key(&key_bytes));
+            }
+        }
+        Ok(invalid)
+    }
+
+    /// Re-serializes every archived [`VersionChanges`] and replaces the stored bytes with the new encoding wherever it's
+    /// smaller, like archives of values written without compression, imported with [`GridDb::insert_version_changes_bytes`]
+    /// or written by older serializers.
+    ///
+    /// Uncompressed inserts are compressed with [`GridDbBuilder::compression`] wherever that makes them smaller. Values that
+    /// are already compressed or checksummed are kept as they are, and so are the blob references of content-addressed maps.
+    /// Every value decompresses to what it was before, so reverting to any version gives the same result as before. Each entry
+    /// is replaced atomically, and only if it wasn't changed since it was read, so a crash part way through leaves every
+    /// archive valid.
+    ///
+    /// This reads the entire version change tree, so it's meant for offline maintenance.
+    pub fn compact_changes(&self) -> Result<CompactionReport, sled::Error> {
+        let mut report = CompactionReport::default();
+        for iter_result in self.version_change_tree.iter() {
+            let (key_bytes, bytes) = iter_result?;
+            report.entries_scanned += 1;
+            let changes = unsafe { ArchivedIVec::<VersionChanges<K>>::new(bytes.clone()) };
+            let mut changes = changes.deserialize();
+            if !self.content_addressed {
+                for change in changes.changes.values_mut() {
+                    if let Change::Insert(value) = change {
+                        let compressed = Change::Insert(value.clone()).compress(self.compression);
+                        if let Change::CompressedInsert(payload) = &compressed {
+                            if payload.len() < value.len() {
+                                *change = compressed;
+                            }
+                        }
+                    }
+                }
+            }
+            let compacted = serialize_version_changes(&changes, 0);
+            if compacted.len() >= bytes.len() {
+                continue;
+            }
+            let swapped = self.version_change_tree.compare_and_swap(
+                &key_bytes,
+                Some(&bytes),
+                Some(compacted.as_ref()),
+            )?;
+            // Someone else replaced the entry since we read it, so it's theirs to keep.
+            if swapped.is_ok() {
+                report.entries_rewritten += 1;
+                report.bytes_saved += (bytes.len() - compacted.len()) as u64;
+            }
+        }
+        Ok(report)
+    }
+
+    /// The [`CommitMeta`] recorded for `version`, or `None` if `version` hasn't been committed.
+    pub fn commit_meta(&self, version: Version) -> Result<Option<CommitMeta>, sled::Error> {
+        Ok(get_version_node(&self.version_graph_tree, version)?.map(|node| node.commit_meta()))
+    }
+
+    /// Points the tag `name` at version `v`, replacing any version it previously pointed to.
+    pub fn tag_version(&mut self, name: &str, v: Version) -> Result<(), sled::Error> {
+        write_tag(&self.tag_tree, name, v)
+    }
+
+    /// Resolves the tag `name`.
+    ///
+    /// Tags are not updated when versions are removed, so the returned version might no longer exist. Use
+    /// [`GridDb::contains_version`] to find out.
+    pub fn version_for_tag(&self, name: &str) -> Result<Option<Version>, sled::Error> {
+        read_tag(&self.tag_tree, name)
+    }
+
+    /// All tags and the versions they point to, sorted by name.
+    pub fn list_tags(&self) -> Result<Vec<(String, Version)>, sled::Error> {
+        read_all_tags(&self.tag_tree)
+    }
+
+    /// Returns `true` if `v` is the working version or has been committed to the version graph.
+    pub fn contains_version(&self, v: Version) -> Result<bool, sled::Error> {
+        Ok(v == self.cached_meta.working_version
+            || self.version_graph_tree.contains_key(v.into_sled_key())?)
+    }
+
+    /// Resolves the tag `name` and then calls [`GridDb::branch_from_version`].
+    ///
+    /// Aborts with [`AbortReason::MissingTag`] if there is no such tag.
+    pub fn branch_from_tag(&mut self, name: &str) -> Result<(), TransactionError<AbortReason>> {
+        if let Some(version) = self.version_for_tag(name)? {
+            self.branch_from_version(version)
+        } else {
+            Err(TransactionError::Abort(AbortReason::MissingTag))
+        }
+    }
+
+    /// Sets the parent version to `new_parent_version` and generates a new (empty) working child version.
+    ///
+    /// This will always `commit_working_version` before migrating to a new parent. If there is no parent for the current
+    /// working version, then nothing happens.
+    pub fn branch_from_version(
+        &mut self,
+        new_parent_version: Version,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        self.retry_on_meta_conflict(|map| map.try_branch_from_version(new_parent_version))
+    }
+
+    /// Like [`GridDb::branch_from_version`], but without retrying after an [`AbortReason::MetaConflict`].
+    fn try_branch_from_version(
+        &mut self,
+        new_parent_version: Version,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        // After committing, we may end up with a new empty working version. But it's not linked into the graph yet. We can just
+        // abandon it, since it is empty.
+        self.try_commit_working_version(&CommitMeta::now())?;
+
+        let old_meta = self.cached_meta;
+
+        if let Some(old_parent_version) = old_meta.parent_version {
+            let new_meta = self.retry_policy.run(|| {
+                (
+                    &self.meta_tree,
+                    &self.version_graph_tree,
+                    &self.version_change_tree,
+                    &self.working_tree,
+                )
+                    .transaction(
+                        |(meta_txn, graph_txn, change_txn, working_txn)| {
+                            check_meta_generation(meta_txn, &old_meta)?;
+                            // Apply the archived changes from all versions between the old parent version and the new
+                            // parent version, leaving behind the inverse changes.
+                            let path = find_path_between_versions(
+                                graph_txn,
+                                old_parent_version,
+                                new_parent_version,
+                            )?;
+                            let empty_backup_keys: BackupKeyCache<K> = BackupKeyCache {
+                                keys: BTreeSet::default(),
+                            };
+                            log::trace!(
+                                "Migrating from parent {:?} to parent {:?}",
+                                old_parent_version,
+                                new_parent_version
+                            );
+                            for (&prev_version, &next_version) in path.path.iter().tuple_windows() {
+                                if let Some(changes) =
+                                    remove_archived_version::<K>(change_txn, next_version)?
+                                {
+                                    let reverse_changes = write_changes_to_working_tree(
+                                        working_txn,
+                                        &empty_backup_keys,
+                                        encode_archived_changes(changes.as_ref()),
+                                    )?;
+                                    let prev_version_changes =
+                                        VersionChanges::<K>::from(&reverse_changes);
+                                    log::trace!("Archiving {:?} from working tree", prev_version,);
+                                    archive_version(
+                                        change_txn,
+                                        prev_version,
+                                        &prev_version_changes,
+                                        self.scratch_capacity,
+                                    )?;
+                                } else {
+                                    return abort(AbortReason::MissingVersionChanges);
+                                }
+                            }
+                            let new_working_version = generate_version(meta_txn)?;
+                            let new_meta = GridDbMetadata {
+                                grandparent_version: path.end_parent,
+                                parent_version: Some(new_parent_version),
+                                working_version: new_working_version,
+                                meta_generation: old_meta.meta_generation + 1,
+                            };
+                            write_meta(meta_txn, &new_meta)?;
+                            Ok(new_meta)
+                        },
+                    )
+            })?;
+            self.cached_meta = new_meta;
+            // Any key on the path may have changed, so it's simpler to start over.
+            if let Some(cache) = &self.read_cache {
+                cache.lock().unwrap().clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Undoes the last commit by branching from the grandparent version, returning the parent version that was reverted away
+    /// from. Nothing happens and `None` is returned if the parent version is a root or there is no parent version.
+    ///
+    /// Like [`GridDb::branch_from_version`], any uncommitted changes are committed first, so they remain reachable as a child
+    /// of the reverted version.
+    pub fn revert_last_commit(&mut self) -> Result<Option<Version>, TransactionError<AbortReason>> {
+        let GridDbMetadata {
+            grandparent_version,
+            parent_version,
+            ..
+        } = self.cached_meta;
+        match (parent_version, grandparent_version) {
+            (Some(parent_version), Some(grandparent_version)) => {
+                self.branch_from_version(grandparent_version)?;
+                Ok(Some(parent_version))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Like [`GridDb::write_working_version`], but writes to several maps in a single transaction, so either all of the maps are
+/// changed or none of them are.
+///
+/// The maps must belong to the same [`sled::Db`]. The transaction follows the [`RetryPolicy`] of the first map, except that an
+/// [`AbortReason::MetaConflict`] is returned instead of retried.
+pub fn write_working_versions<K>(
+    maps: &mut [(&mut GridDb<K>, EncodedChanges)],
+) -> Result<(), TransactionError<AbortReason>>
+where
+    K: DbKey,
+    Archived<K>: Deserialize<K, Infallible> + Ord,
+{
+    for (map, changes) in maps.iter_mut() {
+        *changes = map.compress_inserts(std::mem::take(changes));
+    }
+    let trees: Vec<&Tree> = maps
+        .iter()
+        .flat_map(|(map, _)| {
+            log::trace!("Writing to {:?}", map.cached_meta.working_version);
+            [
+                &map.working_tree,
+                &map.backup_tree,
+                &map.meta_tree,
+                &map.blob_tree,
+            ]
+        })
+        .collect();
+    // There is only one transaction, so it follows the first map's policy.
+    let retry_policy = maps
+        .first()
+        .map(|(map, _)| map.retry_policy)
+        .unwrap_or_default();
+    let new_backup_keys: Vec<Vec<K>> = retry_policy.run(|| {
+        trees.as_slice().transaction(|txns| {
+            let mut new_backup_keys = Vec::with_capacity(maps.len());
+            for ((map, changes), map_txns) in maps.iter().zip(txns.chunks_exact(4)) {
+                new_backup_keys.push(map.write_changes_in_txn(
+                    [&map_txns[0], &map_txns[1], &map_txns[2], &map_txns[3]],
+                    changes.clone(),
+                    true,
+                )?);
+            }
+            Ok(new_backup_keys)
+        })
+    })?;
+    // Transaction succeeded, so add the new keys to the backup caches.
+    for ((map, changes), keys) in maps.iter_mut().zip(new_backup_keys.into_iter()) {
+        map.insert_backup_keys(keys);
+        map.invalidate_cached(changes);
+    }
+    Ok(())
+}
+
+/// Validates that `bytes` hold an archived [`Change`].
+fn is_valid_change(bytes: &[u8]) -> bool {
+    // The validator checks alignment, but sled only guarantees 8-byte alignment of values.
+    let mut aligned = AlignedVec::with_capacity(bytes.len());
+    aligned.extend_from_slice(bytes);
+    check_archived_root::<Change>(&aligned).is_ok()
+}
+
+/// Whether two stored values stand for the same value, regardless of compression. Values that can't be decompressed are
+/// only the same if they are stored the same way.
+fn same_value(a: &Change, b: &Change) -> bool {
+    a == b
+        || matches!(
+            (a.clone().decompress(), b.clone().decompress()),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+/// Plain writes never abort, so only storage errors need to be converted.
+fn with_abort_reason(error: TransactionError) -> TransactionError<AbortReason> {
+    match error {
+        TransactionError::Abort(_) => unreachable!("Writes never abort"),
+        TransactionError::Storage(e) => TransactionError::Storage(e),
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup_tree::read_persisted_backup_keys;
+    use crate::blob_tree::{blob_hash, blob_ref_count};
+    use crate::{DbKey2i32, DbKey3i32};
+
+    use ilattice::glam::{IVec2, IVec3};
+
+    #[test]
+    fn write_and_read_changes_same_version() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let chunk_key = DbKey3i32::new(1, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key, Change::Insert(Box::new([0])));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let chunk_compressed_bytes = map.read_working_version(chunk_key).unwrap().unwrap();
+        assert_eq!(
+            chunk_compressed_bytes.deserialize(),
+            Change::Insert(Box::new([0]))
+        );
+    }
+
+    #[test]
+    fn write_and_read_owned_changes_same_version() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let chunk_key = DbKey3i32::new(1, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key, Change::Insert(Box::new([0])));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        assert_eq!(
+            map.read_working_version_owned(chunk_key).unwrap(),
+            Some(Change::Insert(Box::new([0])))
+        );
+    }
+
+    #[test]
+    fn iter_working_tiles_groups_by_containing_tile() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let key = |level, p: [i32; 3]| DbKey3i32::new(level, IVec3::from(p).into());
+        let mut encoder = ChangeEncoder::default();
+        for k in [
+            key(0, [0, 0, 0]),
+            key(0, [1, 1, 1]),
+            key(0, [2, 0, 0]),
+            key(0, [3, 1, 0]),
+            key(0, [-1, 0, 0]),
+            key(1, [1, 0, 0]),
+            // Above the tile level, so it's skipped.
+            key(2, [0, 0, 0]),
+        ] {
+            encoder.add_change(k, Change::Insert(Box::new([0])));
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let mut tiles: Vec<_> = map
+            .iter_working_tiles(1)
+            .map(|group| {
+                let (tile, entries) = group.unwrap();
+                let mut keys: Vec<_> = entries.into_iter().map(|(k, _)| k).collect();
+                keys.sort();
+                (tile, keys)
+            })
+            .collect();
+        // Each tile comes once, in Morton order.
+        assert!(tiles
+            .windows(2)
+            .all(|pair| pair[0].0.as_sled_key() < pair[1].0.as_sled_key()));
+        tiles.sort();
+
+        let mut expected = vec![
+            (
+                key(1, [0, 0, 0]),
+                vec![key(0, [0, 0, 0]), key(0, [1, 1, 1])],
+            ),
+            (
+                key(1, [1, 0, 0]),
+                vec![key(0, [2, 0, 0]), key(0, [3, 1, 0]), key(1, [1, 0, 0])],
+            ),
+            (key(1, [-1, 0, 0]), vec![key(0, [-1, 0, 0])]),
+        ];
+        expected.sort();
+        assert_eq!(tiles, expected);
+    }
+
+    #[test]
+    fn changing_a_cell_only_changes_its_level_hash() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        for level in 0..3 {
+            encoder.add_change(
+                DbKey3i32::new(level, IVec3::ZERO.into()),
+                Change::Insert(Box::new([level])),
+            );
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+        let before = map.level_content_hashes().unwrap();
+        assert_eq!(before.len(), 3);
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(
+            DbKey3i32::new(1, IVec3::ZERO.into()),
+            Change::Insert(Box::new([9])),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+        let after = map.level_content_hashes().unwrap();
+
+        assert_eq!(before[&0], after[&0]);
+        assert_ne!(before[&1], after[&1]);
+        assert_eq!(before[&2], after[&2]);
+    }
+
+    #[test]
+    fn remove_extent_and_revert() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let key = |x, y| DbKey3i32::new(0, IVec3::new(x, y, 0).into());
+        let mut encoder = ChangeEncoder::default();
+        for y in 0..4 {
+            for x in 0..4 {
+                encoder.add_change(key(x, y), Change::Insert(Box::new([0])));
+            }
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+        let filled_version = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        let removed = map
+            .remove_extent(
+                0,
+                Extent::from_min_and_shape(IVec3::new(1, 1, 0), IVec3::new(2, 2, 1)),
+            )
+            .unwrap();
+        assert_eq!(removed, 4);
+        map.commit_working_version().unwrap();
+
+        for y in 0..4 {
+            for x in 0..4 {
+                let inside = (1..3).contains(&x) && (1..3).contains(&y);
+                assert_eq!(
+                    map.read_working_version(key(x, y)).unwrap().is_some(),
+                    !inside,
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+
+        map.branch_from_version(filled_version).unwrap();
+        for y in 0..4 {
+            for x in 0..4 {
+                assert!(map.read_working_version(key(x, y)).unwrap().is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn reopen_loads_persisted_backup_keys() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let keys = [IVec3::ZERO, IVec3::ONE].map(|p| DbKey3i32::new(1, p.into()));
+        for key in keys {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([0])));
+            map.write_working_version(encoder.encode()).unwrap();
+        }
+        let expected_keys = map.backup_key_cache.keys.clone();
+        assert_eq!(expected_keys, BTreeSet::from(keys));
+        drop(map);
+
+        let persisted =
+            read_persisted_backup_keys::<DbKey3i32>(&db.open_tree("mymap-meta").unwrap())
+                .unwrap()
+                .unwrap();
+        assert_eq!(persisted.keys, expected_keys);
+
+        let mut map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+        assert_eq!(map.backup_key_cache.keys, expected_keys);
+
+        // Committing clears the persisted keys along with the backup tree.
+        map.commit_working_version().unwrap();
+        drop(map);
+        let map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+        assert!(map.backup_key_cache.keys.is_empty());
+    }
+
+    #[test]
+    fn cherry_pick_version_from_sibling_branch() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2, key3] =
+            [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        let write = |map: &mut GridDb<DbKey3i32>, key: DbKey3i32, value: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([value])));
+            map.write_working_version(encoder.encode()).unwrap();
+        };
+
+        write(&mut map, key1, 1);
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+        write(&mut map, key2, 2);
+        let v1 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        // Start a sibling branch of v1 with some unrelated and some conflicting changes.
+        map.branch_from_version(v0).unwrap();
+        write(&mut map, key2, 9);
+        write(&mut map, key3, 3);
+
+        map.cherry_pick(v1).unwrap();
+
+        let read = |key| map.read_working_version_owned(key).unwrap();
+        assert_eq!(read(key1), Some(Change::Insert(Box::new([1]))));
+        assert_eq!(read(key2), Some(Change::Insert(Box::new([2]))));
+        assert_eq!(read(key3), Some(Change::Insert(Box::new([3]))));
+        assert_eq!(map.cached_meta().parent_version, Some(v0));
+
+        // The cherry-picked changes are reverted like any other write.
+        map.commit_working_version().unwrap();
+        map.branch_from_version(v0).unwrap();
+        assert_eq!(map.read_working_version_owned(key2).unwrap(), None);
+    }
+
+    #[test]
+    fn graph_stats_of_branched_history() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        assert_eq!(map.graph_stats().unwrap(), GraphStats::default());
+
+        let commit_change = |map: &mut GridDb<DbKey3i32>, value: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(
+                DbKey3i32::new(0, IVec3::ZERO.into()),
+                Change::Insert(Box::new([value])),
+            );
+            map.write_working_version(encoder.encode()).unwrap();
+            let version = map.cached_meta().working_version;
+            map.commit_working_version().unwrap();
+            version
+        };
+
+        // v0 -> v1
+        //    -> v2 -> v3
+        let v0 = commit_change(&mut map, 0);
+        commit_change(&mut map, 1);
+        map.branch_from_version(v0).unwrap();
+        commit_change(&mut map, 2);
+        commit_change(&mut map, 3);
+
+        assert_eq!(
+            map.graph_stats().unwrap(),
+            GraphStats {
+                node_count: 4,
+                root_count: 1,
+                leaf_count: 2,
+                max_depth: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn cherry_pick_without_archived_changes_aborts() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(
+            DbKey3i32::new(0, IVec3::ZERO.into()),
+            Change::Insert(Box::new([0])),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        // The parent version's state is the base of the working version, so it has no archived changes.
+        assert_eq!(
+            map.cherry_pick(v0),
+            Err(TransactionError::Abort(AbortReason::MissingVersionChanges))
+        );
+    }
+
+    #[test]
+    fn cherry_pick_of_ancestor_aborts() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let mut versions = Vec::new();
+        for value in [0, 1] {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([value])));
+            map.write_working_version(encoder.encode()).unwrap();
+            versions.push(map.cached_meta().working_version);
+            map.commit_working_version().unwrap();
+        }
+
+        // v0's archive holds the value that v1 replaced, so applying it would undo v1.
+        assert_eq!(
+            map.cherry_pick(versions[0]),
+            Err(TransactionError::Abort(AbortReason::AncestorVersion))
+        );
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+    }
+
+    #[test]
+    fn create_resolve_overwrite_and_list_tags() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let chunk_key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key, Change::Insert(Box::new([0])));
+        map.write_working_version(encoder.encode()).unwrap();
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key, Change::Insert(Box::new([1])));
+        map.write_working_version(encoder.encode()).unwrap();
+        let v1 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        assert_eq!(map.version_for_tag("before-raid").unwrap(), None);
+        assert_eq!(
+            map.branch_from_tag("before-raid"),
+            Err(TransactionError::Abort(AbortReason::MissingTag))
+        );
+
+        map.tag_version("before-raid", v1).unwrap();
+        map.tag_version("release-1.2", v1).unwrap();
+        assert_eq!(map.version_for_tag("before-raid").unwrap(), Some(v1));
+
+        // Overwrite.
+        map.tag_version("before-raid", v0).unwrap();
+        assert_eq!(map.version_for_tag("before-raid").unwrap(), Some(v0));
+
+        assert_eq!(
+            map.list_tags().unwrap(),
+            vec![
+                ("before-raid".to_owned(), v0),
+                ("release-1.2".to_owned(), v1)
+            ]
+        );
+
+        map.branch_from_tag("before-raid").unwrap();
+        assert_eq!(
+            map.read_working_version_owned(chunk_key).unwrap(),
+            Some(Change::Insert(Box::new([0])))
+        );
+
+        // Tags can point at versions that don't exist.
+        map.tag_version("bogus", Version::new(1000)).unwrap();
+        assert_eq!(
+            map.version_for_tag("bogus").unwrap(),
+            Some(Version::new(1000))
+        );
+        assert!(!map.contains_version(Version::new(1000)).unwrap());
+        assert!(map.contains_version(v0).unwrap());
+    }
+
+    #[test]
+    fn write_without_backup_leaves_backup_untouched() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let backed_up_key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let unbacked_key = DbKey3i32::new(0, IVec3::ONE.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(backed_up_key, Change::Insert(Box::new([0])));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let backup_len = map.backup_tree.len();
+        let backup_keys = map.backup_key_cache.keys.clone();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(unbacked_key, Change::Insert(Box::new([1])));
+        encoder.add_change(backed_up_key, Change::Remove);
+        map.write_working_version_no_backup(encoder.encode())
+            .unwrap();
+
+        assert_eq!(map.backup_tree.len(), backup_len);
+        assert_eq!(map.backup_key_cache.keys, backup_keys);
+        assert_eq!(map.read_working_version_owned(backed_up_key).unwrap(), None);
+        assert_eq!(
+            map.read_working_version_owned(unbacked_key).unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+    }
+
+    #[test]
+    fn version_touched_levels_of_leaf() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(
+            DbKey3i32::new(1, IVec3::ZERO.into()),
+            Change::Insert(Box::new([0])),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        for (level, p) in [(0, IVec3::ZERO), (0, IVec3::ONE), (2, IVec3::ZERO)] {
+            encoder.add_change(
+                DbKey3i32::new(level, p.into()),
+                Change::Insert(Box::new([1])),
+            );
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+        let v1 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        // Move away from v1 so its changes get archived.
+        map.branch_from_version(v0).unwrap();
+
+        assert_eq!(
+            map.version_touched_levels(v1).unwrap(),
+            BTreeSet::from([0, 2])
+        );
+    }
+
+    #[test]
+    fn committed_versions_carry_metadata() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let chunk_key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key, Change::Insert(Box::new([0])));
+        map.write_working_version(encoder.encode()).unwrap();
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key, Change::Insert(Box::new([1])));
+        map.write_working_version(encoder.encode()).unwrap();
+        let v1 = map.cached_meta().working_version;
+        let meta = CommitMeta {
+            created_at: Some(1234),
+            label: Some("alice".to_owned()),
+        };
+        map.commit_working_version_with(meta.clone()).unwrap();
+
+        assert!(map.commit_meta(v0).unwrap().unwrap().created_at.is_some());
+        assert_eq!(map.commit_meta(v0).unwrap().unwrap().label, None);
+        assert_eq!(map.commit_meta(v1).unwrap(), Some(meta));
+        assert_eq!(
+            map.commit_meta(map.cached_meta().working_version).unwrap(),
+            None
+        );
+
+        // Traversal still works.
+        map.branch_from_version(v0).unwrap();
+        assert_eq!(
+            map.read_working_version_owned(chunk_key).unwrap(),
+            Some(Change::Insert(Box::new([0])))
+        );
+    }
+
+    #[test]
+    fn for_each_working_raw_visits_every_entry() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        for i in 0..10 {
+            encoder.add_change(
+                DbKey3i32::new(0, IVec3::new(i, 0, 0).into()),
+                Change::Insert(Box::new([i as u8])),
+            );
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let mut count = 0;
+        let mut level_one_count = 0;
+        map.for_each_working_raw(|key_bytes, _value| {
+            count += 1;
+            if key_bytes[0] == 1 {
+                level_one_count += 1;
+            }
+        })
+        .unwrap();
+        assert_eq!(count, 10);
+        assert_eq!(count, map.working_len());
+        assert_eq!(level_one_count, 0);
+    }
+
+    #[test]
+    fn stats_count_entries_per_level() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        for (level, count) in [(0, 5), (1, 3), (4, 1)] {
+            for i in 0..count {
+                encoder.add_change(
+                    DbKey3i32::new(level, IVec3::new(i, 0, 0).into()),
+                    Change::Insert(Box::new([0])),
+                );
+            }
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let stats = map.stats().unwrap();
+        assert_eq!(
+            stats,
+            GridDbStats {
+                working_entry_count: 9,
+                backup_entry_count: 9,
+                archived_version_count: 0,
+                entries_per_level: BTreeMap::from([(0, 5), (1, 3), (4, 1)]),
+            }
+        );
+
+        map.commit_working_version().unwrap();
+        let stats = map.stats().unwrap();
+        assert_eq!(stats.working_entry_count, 9);
+        assert_eq!(stats.backup_entry_count, 0);
+    }
+
+    #[test]
+    fn validate_all_archives_reports_corrupt_versions() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let mut versions = Vec::new();
+        for i in 0..4 {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([i])));
+            map.write_working_version(encoder.encode()).unwrap();
+            versions.push(map.cached_meta().working_version);
+            map.commit_working_version().unwrap();
+        }
+        // Every committed version but the parent is archived.
+        assert_eq!(map.version_change_tree.len(), 3);
+        assert!(map.validate_all_archives().unwrap().is_empty());
+
+        let corrupt_version = versions[1];
+        let len = map
+            .version_change_tree
+            .get(corrupt_version.into_sled_key())
+            .unwrap()
+            .unwrap()
+            .len();
+        map.version_change_tree
+            .insert(corrupt_version.into_sled_key(), vec![0xff; len])
+            .unwrap();
+
+        assert_eq!(map.validate_all_archives().unwrap(), vec![corrupt_version]);
+    }
+
+    #[test]
+    fn working_value_size_matches_stored_bytes() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let present_key = DbKey3i32::new(1, IVec3::ZERO.into());
+        let absent_key = DbKey3i32::new(1, IVec3::ONE.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(present_key, Change::Insert(vec![7; 100].into()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let stored = map.read_working_version(present_key).unwrap().unwrap();
+        assert_eq!(
+            map.working_value_size(present_key).unwrap(),
+            Some(stored.as_bytes().len())
+        );
+        assert!(stored.as_bytes().len() > 100);
+        assert_eq!(map.working_value_size(absent_key).unwrap(), None);
+    }
+
+    #[test]
+    fn write_working_versions_to_multiple_maps() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut terrain = GridDb::open(&db, "terrain").unwrap();
+        let mut entities = GridDb::open(&db, "entities").unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let encode = |value: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([value])));
+            encoder.encode()
+        };
+
+        // A failure in the second map after the first was written must abort the writes to both. Committing through
+        // another handle makes this one stale, so writing through it aborts.
+        let mut stale_entities = GridDb::open(&db, "entities").unwrap();
+        entities.commit_working_version().unwrap();
+        let result = write_working_versions(&mut [
+            (&mut terrain, encode(1)),
+            (&mut stale_entities, encode(2)),
+        ]);
+        assert_eq!(
+            result,
+            Err(TransactionError::Abort(AbortReason::MetaConflict))
+        );
+        for map in [&terrain, &entities] {
+            assert_eq!(map.read_working_version(key).unwrap(), None);
+            assert!(map.backup_tree.is_empty());
+            assert!(map.backup_key_cache.keys.is_empty());
+        }
+
+        write_working_versions(&mut [(&mut terrain, encode(1)), (&mut entities, encode(2))])
+            .unwrap();
+        assert_eq!(
+            terrain.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+        assert_eq!(
+            entities.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([2])))
+        );
+        for map in [&terrain, &entities] {
+            assert_eq!(map.backup_key_cache.keys, BTreeSet::from([key]));
+        }
+    }
+
+    fn blob_refs(map: &GridDb<DbKey3i32>, value: &Change) -> u64 {
+        blob_ref_count(&map.blob_tree, &blob_hash(value.serialize().as_ref())).unwrap()
+    }
+
+    #[test]
+    fn content_addressed_dedup_and_gc() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDbBuilder::default()
+            .content_addressed(true)
+            .open(&db, "mymap")
+            .unwrap();
+
+        let [key1, key2] = [0, 1].map(|i| DbKey3i32::new(0, IVec3::new(i, 0, 0).into()));
+        let value = Change::Insert(vec![9; 1000].into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, value.clone());
+        encoder.add_change(key2, value.clone());
+        map.write_working_version(encoder.encode()).unwrap();
+
+        // One blob (plus its reference count) for both keys.
+        assert_eq!(map.blob_tree.len(), 2);
+        assert_eq!(blob_refs(&map, &value), 2);
+        assert_eq!(
+            map.read_working_version_owned(key1).unwrap(),
+            Some(value.clone())
+        );
+        assert_eq!(
+            map.read_working_version_owned(key2).unwrap(),
+            Some(value.clone())
+        );
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Remove);
+        map.write_working_version(encoder.encode()).unwrap();
+        assert_eq!(blob_refs(&map, &value), 1);
+        assert_eq!(map.read_working_version(key1).unwrap(), None);
+        assert_eq!(
+            map.read_working_version_owned(key2).unwrap(),
+            Some(value.clone())
+        );
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key2, Change::Remove);
+        map.write_working_version(encoder.encode()).unwrap();
+        assert_eq!(blob_refs(&map, &value), 0);
+        assert!(map.blob_tree.is_empty());
+    }
+
+    #[test]
+    fn content_addressed_history_keeps_blobs() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDbBuilder::default()
+            .content_addressed(true)
+            .open(&db, "mymap")
+            .unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let value = Change::Insert(Box::new([1, 2, 3]));
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key, value.clone());
+        map.write_working_version(encoder.encode()).unwrap();
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key, Change::Remove);
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap();
+
+        // The removed value is still referenced by the archive of v0.
+        assert_eq!(map.read_working_version(key).unwrap(), None);
+        assert_eq!(blob_refs(&map, &value), 1);
+
+        map.branch_from_version(v0).unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(value.clone())
+        );
+        assert_eq!(blob_refs(&map, &value), 1);
+    }
+
+    #[test]
+    fn cell_count_delta_between_versions() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [k0, k1, k2, k3, k4] =
+            [0, 1, 2, 3, 4].map(|i| DbKey3i32::new(0, IVec3::new(i, 0, 0).into()));
+        let commit = |map: &mut GridDb<DbKey3i32>, changes: Vec<(DbKey3i32, Change)>| {
+            let mut encoder = ChangeEncoder::default();
+            for (key, change) in changes {
+                encoder.add_change(key, change);
+            }
+            map.write_working_version(encoder.encode()).unwrap();
+            let version = map.cached_meta().working_version;
+            map.commit_working_version().unwrap();
+            version
+        };
+        let insert = || Change::Insert(Box::new([0]));
+
+        let v0 = commit(&mut map, vec![(k0, insert())]);
+        // Nets +2 cells.
+        let v1 = commit(
+            &mut map,
+            vec![
+                (k1, insert()),
+                (k2, insert()),
+                (k3, insert()),
+                (k0, Change::Remove),
+            ],
+        );
+        assert_eq!(map.cell_count_delta(v0, v1), Ok(2));
+        assert_eq!(map.cell_count_delta(v1, v0), Ok(-2));
+        assert_eq!(map.cell_count_delta(v1, v1), Ok(0));
+
+        // A sibling branch, which also moves the parent version.
+        map.branch_from_version(v0).unwrap();
+        let v2 = commit(&mut map, vec![(k4, insert())]);
+        assert_eq!(map.cell_count_delta(v0, v1), Ok(2));
+        assert_eq!(map.cell_count_delta(v0, v2), Ok(1));
+        assert_eq!(map.cell_count_delta(v1, v2), Ok(-1));
+    }
+
+    #[test]
+    fn contains_working_version_tracks_inserts_and_removes() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [present_key, removed_key, absent_key] =
+            [0, 1, 2].map(|i| DbKey3i32::new(0, IVec3::new(i, 0, 0).into()));
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(present_key, Change::Insert(Box::new([0])));
+        encoder.add_change(removed_key, Change::Insert(Box::new([0])));
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(removed_key, Change::Remove);
+        map.write_working_version(encoder.encode()).unwrap();
+
+        assert!(map.contains_working_version(present_key).unwrap());
+        assert!(!map.contains_working_version(removed_key).unwrap());
+        assert!(!map.contains_working_version(absent_key).unwrap());
+
+        // Checking doesn't bring back the removed entry.
+        assert_eq!(map.read_working_version(removed_key).unwrap(), None);
+        assert!(!map.contains_working_version(removed_key).unwrap());
+    }
+
+    #[test]
+    fn preview_matches_written_backup() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [k0, k1, k2, k3] = [0, 1, 2, 3].map(|i| DbKey3i32::new(0, IVec3::new(i, 0, 0).into()));
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(k0, Change::Insert(Box::new([0])));
+        encoder.add_change(k2, Change::Insert(Box::new([2])));
+        map.write_working_version(encoder.encode()).unwrap();
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        // Insert over present and absent keys.
+        encoder.add_change(k0, Change::Insert(Box::new([10])));
+        encoder.add_change(k1, Change::Insert(Box::new([11])));
+        // Remove present and absent keys.
+        encoder.add_change(k2, Change::Remove);
+        encoder.add_change(k3, Change::Remove);
+        let changes = encoder.encode();
+
+        let preview = map.preview_working_version(&changes).unwrap();
+        assert_eq!(
+            preview.changes,
+            BTreeMap::from([
+                (k0, Change::Insert(Box::new([0]))),
+                (k1, Change::Remove),
+                (k2, Change::Insert(Box::new([2]))),
+                (k3, Change::Remove),
+            ])
+        );
+        // Nothing was written.
+        assert_eq!(
+            map.read_working_version_owned(k0).unwrap(),
+            Some(Change::Insert(Box::new([0])))
+        );
+        assert!(map.backup_tree.is_empty());
+
+        // The backup gets archived with the parent version on commit.
+        map.write_working_version(changes.clone()).unwrap();
+        map.commit_working_version().unwrap();
+        let archived = unsafe {
+            ArchivedIVec::<VersionChanges<DbKey3i32>>::new(
+                map.version_change_tree
+                    .get(v0.into_sled_key())
+                    .unwrap()
+                    .unwrap(),
+            )
+        };
+        assert_eq!(archived.deserialize(), preview);
+
+        // Keys already changed in the working version are not backed up again.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(k0, Change::Insert(Box::new([20])));
+        map.write_working_version(encoder.encode()).unwrap();
+        let preview = map.preview_working_version(&changes).unwrap();
+        assert!(!preview.changes.contains_key(&k0));
+        assert_eq!(preview.changes.len(), 3);
+    }
+
+    #[test]
+    fn export_and_import_branched_map() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2, key3] =
+            [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        let write = |map: &mut GridDb<DbKey3i32>, key: DbKey3i32, value: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([value])));
+            map.write_working_version(encoder.encode()).unwrap();
+        };
+
+        // v0 -> v1
+        //    -> v2 -> (uncommitted working version)
+        write(&mut map, key1, 1);
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+        write(&mut map, key2, 2);
+        let v1 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+        map.branch_from_version(v0).unwrap();
+        write(&mut map, key2, 3);
+        map.commit_working_version().unwrap();
+        write(&mut map, key3, 4);
+        map.tag_version("first", v0).unwrap();
+
+        let mut exported = Vec::new();
+        map.export(&mut exported).unwrap();
+
+        let other_db = sled::Config::default().temporary(true).open().unwrap();
+        let mut imported =
+            GridDb::<DbKey3i32>::import(&other_db, "copy", exported.as_slice()).unwrap();
+        assert_eq!(imported.cached_meta(), map.cached_meta());
+        assert_eq!(imported.graph_stats().unwrap(), map.graph_stats().unwrap());
+        assert_eq!(imported.list_tags().unwrap(), map.list_tags().unwrap());
+        assert_eq!(imported.stats().unwrap(), map.stats().unwrap());
+
+        let read_all = |map: &GridDb<DbKey3i32>| {
+            [key1, key2, key3].map(|key| map.read_working_version_owned(key).unwrap())
+        };
+        assert_eq!(read_all(&imported), read_all(&map));
+
+        // The uncommitted changes are reverted, and the other branch is reachable.
+        let imported_working_version = imported.cached_meta().working_version;
+        map.branch_from_version(v1).unwrap();
+        imported.branch_from_version(v1).unwrap();
+        assert_eq!(read_all(&imported), read_all(&map));
+        assert_eq!(
+            read_all(&imported),
+            [
+                Some(Change::Insert(Box::new([1]))),
+                Some(Change::Insert(Box::new([2]))),
+                None
+            ]
+        );
+        // New versions don't collide with imported ones.
+        assert!(imported.cached_meta().working_version > imported_working_version);
+
+        // Can't import over an existing map.
+        assert!(matches!(
+            GridDb::<DbKey3i32>::import(&other_db, "copy", exported.as_slice()),
+            Err(ImportError::MapExists)
+        ));
+    }
+
+    #[test]
+    fn import_rejects_truncated_sections() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        for len in [u64::MAX, 1 << 40, 64] {
+            let mut exported = Vec::new();
+            write_header(&mut exported, 0, 1).unwrap();
+            exported.extend_from_slice(&len.to_le_bytes());
+            exported.extend_from_slice(&[0; 16]);
+            assert!(matches!(
+                GridDb::<DbKey3i32>::import(&db, "copy", exported.as_slice()),
+                Err(ImportError::InvalidFormat("truncated section"))
+            ));
+        }
+    }
+
+    #[test]
+    fn copy_extent_between_maps_and_revert() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut source = GridDb::open(&db, "template").unwrap();
+        let mut dest = GridDb::open(&db, "live").unwrap();
+
+        let key = |x, y| DbKey3i32::new(0, IVec3::new(x, y, 0).into());
+        let write = |map: &mut GridDb<DbKey3i32>, keys: &[(i32, i32)], value: u8| {
+            let mut encoder = ChangeEncoder::default();
+            for &(x, y) in keys {
+                encoder.add_change(key(x, y), Change::Insert(Box::new([value])));
+            }
+            map.write_working_version(encoder.encode()).unwrap();
+        };
+        let read =
+            |map: &GridDb<DbKey3i32>, x, y| map.read_working_version_owned(key(x, y)).unwrap();
+
+        // The source fills the 2x2 region at the origin except for (1, 1), plus one key outside of it.
+        write(&mut source, &[(0, 0), (1, 0), (0, 1), (3, 3)], 1);
+        write(&mut dest, &[(0, 0), (1, 1)], 2);
+        let dest_version = dest.cached_meta().working_version;
+        dest.commit_working_version().unwrap();
+
+        let extent = Extent::from_min_and_shape(IVec3::ZERO, IVec3::new(2, 2, 1));
+        assert_eq!(dest.copy_extent_from(&source, 0, extent, true).unwrap(), 4);
+        dest.commit_working_version().unwrap();
+        for (x, y) in [(0, 0), (1, 0), (0, 1)] {
+            assert_eq!(read(&dest, x, y), Some(Change::Insert(Box::new([1]))));
+        }
+        assert_eq!(read(&dest, 1, 1), None);
+        assert_eq!(read(&dest, 3, 3), None);
+
+        // Reverting restores the destination's own values.
+        dest.branch_from_version(dest_version).unwrap();
+        assert_eq!(read(&dest, 0, 0), Some(Change::Insert(Box::new([2]))));
+        assert_eq!(read(&dest, 1, 1), Some(Change::Insert(Box::new([2]))));
+        assert_eq!(read(&dest, 1, 0), None);
+        assert_eq!(read(&dest, 0, 1), None);
+
+        // Without replacing, keys missing from the source are kept.
+        assert_eq!(dest.copy_extent_from(&source, 0, extent, false).unwrap(), 3);
+        assert_eq!(read(&dest, 1, 1), Some(Change::Insert(Box::new([2]))));
+    }
+
+    #[test]
+    fn revert_last_commit_twice() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        assert_eq!(map.revert_last_commit().unwrap(), None);
+
+        let mut versions = Vec::new();
+        for value in 0..3 {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([value])));
+            map.write_working_version(encoder.encode()).unwrap();
+            versions.push(map.cached_meta().working_version);
+            map.commit_working_version().unwrap();
+        }
+        let read = |map: &GridDb<DbKey3i32>| map.read_working_version_owned(key).unwrap();
+
+        assert_eq!(map.revert_last_commit().unwrap(), Some(versions[2]));
+        assert_eq!(read(&map), Some(Change::Insert(Box::new([1]))));
+        assert_eq!(map.cached_meta().parent_version, Some(versions[1]));
+
+        assert_eq!(map.revert_last_commit().unwrap(), Some(versions[1]));
+        assert_eq!(read(&map), Some(Change::Insert(Box::new([0]))));
+        assert_eq!(map.cached_meta().parent_version, Some(versions[0]));
+
+        // The root can't be reverted.
+        assert_eq!(map.revert_last_commit().unwrap(), None);
+        assert_eq!(read(&map), Some(Change::Insert(Box::new([0]))));
+    }
+
+    /// Commits a base version, then `theirs` on one branch and `ours` on a sibling branch, leaving the working version on our
+    /// branch. Returns the leaf of their branch.
+    fn diverge(
+        map: &mut GridDb<DbKey3i32>,
+        base: &[(DbKey3i32, u8)],
+        ours: &[(DbKey3i32, u8)],
+        theirs: &[(DbKey3i32, u8)],
+    ) -> Version {
+        let commit = |map: &mut GridDb<DbKey3i32>, changes: &[(DbKey3i32, u8)]| {
+            let mut encoder = ChangeEncoder::default();
+            for &(key, value) in changes {
+                encoder.add_change(key, Change::Insert(Box::new([value])));
+            }
+            map.write_working_version(encoder.encode()).unwrap();
+            let version = map.cached_meta().working_version;
+            map.commit_working_version().unwrap();
+            version
+        };
+        let base_version = commit(map, base);
+        let their_leaf = commit(map, theirs);
+        map.branch_from_version(base_version).unwrap();
+        commit(map, ours);
+        their_leaf
+    }
+
+    #[test]
+    fn merge_disjoint_changes() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key0, key1, key2] =
+            [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        let their_leaf = diverge(&mut map, &[(key0, 0)], &[(key2, 2)], &[(key1, 1)]);
+
+        let report = map.merge(their_leaf).unwrap();
+        assert_eq!(report.applied, vec![key1]);
+        assert!(report.conflicts.is_empty());
+        let read = |key| map.read_working_version_owned(key).unwrap();
+        assert_eq!(read(key0), Some(Change::Insert(Box::new([0]))));
+        assert_eq!(read(key1), Some(Change::Insert(Box::new([1]))));
+        assert_eq!(read(key2), Some(Change::Insert(Box::new([2]))));
+    }
+
+    #[test]
+    fn merge_identical_changes_without_conflict() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key0, key1] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let their_leaf = diverge(&mut map, &[(key0, 0)], &[(key1, 5)], &[(key1, 5)]);
+
+        let report = map.merge(their_leaf).unwrap();
+        assert!(report.applied.is_empty());
+        assert!(report.conflicts.is_empty());
+        assert_eq!(
+            map.read_working_version_owned(key1).unwrap(),
+            Some(Change::Insert(Box::new([5])))
+        );
+    }
+
+    #[test]
+    fn merge_reports_conflicts_and_keeps_ours() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key0, key1, key2] =
+            [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        let their_leaf = diverge(
+            &mut map,
+            &[(key0, 0)],
+            &[(key0, 1), (key1, 5)],
+            &[(key0, 2), (key2, 6)],
+        );
+
+        let report = map.merge(their_leaf).unwrap();
+        assert_eq!(report.applied, vec![key2]);
+        assert_eq!(
+            report.conflicts,
+            vec![MergeConflict {
+                key: key0,
+                ours: Some(Change::Insert(Box::new([1]))),
+                theirs: Some(Change::Insert(Box::new([2]))),
+            }]
+        );
+        let read = |key| map.read_working_version_owned(key).unwrap();
+        assert_eq!(read(key0), Some(Change::Insert(Box::new([1]))));
+        assert_eq!(read(key1), Some(Change::Insert(Box::new([5]))));
+        assert_eq!(read(key2), Some(Change::Insert(Box::new([6]))));
+    }
+
+    #[test]
+    fn read_neighbors_reports_presence() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let center = DbKey3i32::new(1, IVec3::ZERO.into());
+        let below = IVec3::new(0, 0, -1);
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(center, Change::Insert(Box::new([0])));
+        for (i, p) in [IVec3::X, below].into_iter().enumerate() {
+            encoder.add_change(
+                DbKey3i32::new(1, p.into()),
+                Change::Insert(Box::new([i as u8 + 1])),
+            );
+        }
+        // Diagonal and other-level nodes aren't neighbors.
+        encoder.add_change(
+            DbKey3i32::new(1, IVec3::ONE.into()),
+            Change::Insert(Box::new([9])),
+        );
+        encoder.add_change(
+            DbKey3i32::new(0, IVec3::Y.into()),
+            Change::Insert(Box::new([9])),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let neighbors = map.read_neighbors(center).unwrap();
+        assert_eq!(neighbors.len(), 6);
+        for (neighbor, value) in neighbors {
+            let expected = match neighbor.coords() {
+                p if p == IVec3::X => Some(Change::Insert(Box::new([1]))),
+                p if p == below => Some(Change::Insert(Box::new([2]))),
+                _ => None,
+            };
+            assert_eq!(value, expected, "{:?}", neighbor);
+        }
+    }
+
+    #[test]
+    fn with_working_value_borrows_insert_data() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Insert(vec![7; 100].into()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let insert_len = |change: &ArchivedChange| change.get_insert_data().map(|data| data.len());
+        assert_eq!(
+            map.with_working_value(key1, insert_len).unwrap(),
+            Some(Some(100))
+        );
+        assert_eq!(map.with_working_value(key2, insert_len).unwrap(), None);
+    }
+
+    #[test]
+    fn verify_reports_missing_backup_entry() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        for key in [key1, key2] {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([1])));
+            map.write_working_version(encoder.encode()).unwrap();
+            map.commit_working_version().unwrap();
+        }
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Insert(Box::new([2])));
+        encoder.add_change(key2, Change::Insert(Box::new([2])));
+        map.write_working_version(encoder.encode()).unwrap();
+        assert_eq!(map.verify().unwrap(), vec![]);
+
+        map.backup_tree.remove(key2.as_sled_key()).unwrap();
+        map.working_tree
+            .insert(key1.as_sled_key(), vec![1u8, 2, 3])
+            .unwrap();
+        assert_eq!(
+            map.verify().unwrap(),
+            vec![
+                IntegrityIssue::MissingBackupEntry(key2),
+                IntegrityIssue::CorruptWorkingValue(key1),
+            ]
+        );
+    }
+
+    #[test]
+    fn rebuild_backup_cache_after_desync() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2, key3] =
+            [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        let write = |map: &mut GridDb<DbKey3i32>, key: DbKey3i32, value: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([value])));
+            map.write_working_version(encoder.encode()).unwrap();
+        };
+        write(&mut map, key1, 1);
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+        write(&mut map, key1, 2);
+        write(&mut map, key2, 2);
+
+        // One cached key is missing from the tree, and one backed up key is missing from the cache.
+        map.backup_key_cache.keys.remove(&key2);
+        map.backup_key_cache.keys.insert(key3);
+        assert!(!map.verify().unwrap().is_empty());
+
+        assert_eq!(map.rebuild_backup_cache().unwrap(), 2);
+        assert_eq!(map.verify().unwrap(), vec![]);
+        assert_eq!(
+            read_persisted_backup_keys::<DbKey3i32>(&map.meta_tree)
+                .unwrap()
+                .unwrap()
+                .keys,
+            BTreeSet::from([key1, key2])
+        );
+
+        map.commit_working_version().unwrap();
+        map.branch_from_version(v0).unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key1).unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+        assert_eq!(map.read_working_version_owned(key2).unwrap(), None);
+    }
+
+    #[test]
+    fn remove_subtree_and_revert() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        // A full 3-level octree under the root, plus the root's sibling and one of its children.
+        let root = DbKey3i32::new(2, IVec3::ONE.into());
+        let sibling = DbKey3i32::new(2, IVec3::ZERO.into());
+        let mut subtree = vec![root];
+        for child in root.children() {
+            subtree.push(child);
+            subtree.extend(child.children());
+        }
+        assert_eq!(subtree.len(), 1 + 8 + 64);
+        let outside = [sibling, sibling.children()[0]];
+        let mut encoder = ChangeEncoder::default();
+        for &key in subtree.iter().chain(outside.iter()) {
+            encoder.add_change(key, Change::Insert(Box::new([key.level()])));
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+        let filled_version = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        assert_eq!(map.remove_subtree(root, 0).unwrap(), subtree.len());
+        map.commit_working_version().unwrap();
+        for &key in subtree.iter() {
+            assert!(!map.contains_working_version(key).unwrap(), "{:?}", key);
+        }
+        for key in outside {
+            assert!(map.contains_working_version(key).unwrap(), "{:?}", key);
+        }
+
+        map.branch_from_version(filled_version).unwrap();
+        for &key in subtree.iter().chain(outside.iter()) {
+            assert!(map.contains_working_version(key).unwrap(), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn downsample_children_into_parent() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let parent = DbKey3i32::new(1, IVec3::ONE.into());
+        let mut encoder = ChangeEncoder::default();
+        for (i, child) in parent.children().into_iter().enumerate() {
+            encoder.add_change(child, Change::Insert(Box::new([2 * i as u8])));
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let average = |children: &[(DbKey3i32, Option<Change>)]| {
+            let values: Vec<u32> = children
+                .iter()
+                .filter_map(|(_, value)| value.clone())
+                .map(|value| value.unwrap_insert()[0] as u32)
+                .collect();
+            if values.is_empty() {
+                return None;
+            }
+            let mean = values.iter().sum::<u32>() / values.len() as u32;
+            Some(Box::new([mean as u8]) as Box<[u8]>)
+        };
+        map.write_downsampled(parent, average).unwrap();
+        // (0 + 2 + ... + 14) / 8
+        assert_eq!(
+            map.read_working_version_owned(parent).unwrap(),
+            Some(Change::Insert(Box::new([7])))
+        );
+
+        // Without any children, the parent is removed.
+        let empty_parent = DbKey3i32::new(1, IVec3::ZERO.into());
+        map.write_downsampled(empty_parent, average).unwrap();
+        assert_eq!(map.read_working_version_owned(empty_parent).unwrap(), None);
+        assert_eq!(map.read_children(empty_parent).unwrap().len(), 8);
+    }
+
+    #[test]
+    fn transfer_version_changes_bytes_between_dbs() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut server = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Insert(Box::new([1])));
+        server.write_working_version(encoder.encode()).unwrap();
+        let v0 = server.cached_meta().working_version;
+        server.commit_working_version().unwrap();
+
+        // The client starts from the same parent version.
+        let mut exported = Vec::new();
+        server.export(&mut exported).unwrap();
+        let client_db = sled::Config::default().temporary(true).open().unwrap();
+        let mut client =
+            GridDb::<DbKey3i32>::import(&client_db, "mymap", exported.as_slice()).unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Insert(Box::new([2])));
+        encoder.add_change(key2, Change::Insert(Box::new([2])));
+        server.write_working_version(encoder.encode()).unwrap();
+        let v1 = server.cached_meta().working_version;
+        server.commit_working_version().unwrap();
+        // Moving back to v0 archives v1 relative to v0.
+        server.branch_from_version(v0).unwrap();
+        let bytes = server.read_version_changes_bytes(v1).unwrap().unwrap();
+
+        assert_eq!(
+            client.insert_version_changes_bytes(v1, &bytes[1..]),
+            Err(TransactionError::Abort(AbortReason::InvalidVersionChanges))
+        );
+        client.insert_version_changes_bytes(v1, &bytes).unwrap();
+        assert_eq!(
+            client.insert_version_changes_bytes(v1, &bytes),
+            Err(TransactionError::Abort(AbortReason::VersionExists))
+        );
+
+        let read = |map: &GridDb<DbKey3i32>, key| map.read_working_version_owned(key).unwrap();
+        client.branch_from_version(v1).unwrap();
+        assert_eq!(read(&client, key1), Some(Change::Insert(Box::new([2]))));
+        assert_eq!(read(&client, key2), Some(Change::Insert(Box::new([2]))));
+        assert!(client.cached_meta().working_version > v1);
+
+        // Revert across the inserted version.
+        client.branch_from_version(v0).unwrap();
+        assert_eq!(read(&client, key1), Some(Change::Insert(Box::new([1]))));
+        assert_eq!(read(&client, key2), None);
+    }
+
+    #[test]
+    fn insert_version_changes_bytes_rejects_content_addressed_maps() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDbBuilder::<DbKey3i32>::default()
+            .content_addressed(true)
+            .open(&db, "mymap")
+            .unwrap();
+
+        let bytes = serialize_version_changes(&VersionChanges::new(BTreeMap::new()), 0);
+        let version = Version::new(map.cached_meta().working_version.number + 1);
+        assert_eq!(
+            map.insert_version_changes_bytes(version, &bytes),
+            Err(TransactionError::Abort(AbortReason::ContentAddressed))
+        );
+    }
+
+    #[test]
+    fn insert_version_changes_bytes_rejects_working_version() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+        map.commit_working_version().unwrap();
+
+        let bytes = serialize_version_changes(&VersionChanges::new(BTreeMap::new()), 0);
+        let working = map.cached_meta().working_version;
+        assert_eq!(
+            map.insert_version_changes_bytes(working, &bytes),
+            Err(TransactionError::Abort(AbortReason::VersionExists))
+        );
+
+        // The next version the map would generate is taken by the inserted one, so the map skips it.
+        let next = Version::new(working.number + 1);
+        map.insert_version_changes_bytes(next, &bytes).unwrap();
+        map.commit_working_version().unwrap();
+        assert!(map.cached_meta().working_version > next);
+        assert_eq!(map.cached_meta().parent_version, Some(working));
+        assert_eq!(map.graph_stats().unwrap().leaf_count, 2);
+    }
+
+    #[test]
+    fn read_cache_serves_fresh_values_after_writes() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open_with_cache(&db, "mymap", 8).unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let write = |map: &mut GridDb<DbKey3i32>, byte: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([byte])));
+            map.write_working_version(encoder.encode()).unwrap();
+        };
+        assert_eq!(map.read_working_version_owned(key).unwrap(), None);
+        write(&mut map, 1);
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+
+        // Sneak a value past the cache to prove that the next read is a hit.
+        map.working_tree
+            .insert(
+                key.as_sled_key(),
+                Change::Insert(Box::new([9])).serialize().as_ref(),
+            )
+            .unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+
+        write(&mut map, 2);
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([2])))
+        );
+        map.commit_working_version().unwrap();
+        let v2 = map.cached_meta().parent_version.unwrap();
+
+        write(&mut map, 3);
+        map.commit_working_version().unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([3])))
+        );
+
+        map.branch_from_version(v2).unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([2])))
+        );
+    }
+
+    #[test]
+    fn read_cache_respects_capacity() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open_with_cache(&db, "mymap", 2).unwrap();
+
+        let keys = [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        let mut encoder = ChangeEncoder::default();
+        for (i, &key) in keys.iter().enumerate() {
+            encoder.add_change(key, Change::Insert(Box::new([i as u8])));
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+
+        for (i, &key) in keys.iter().enumerate() {
+            assert_eq!(
+                map.read_working_version_owned(key).unwrap(),
+                Some(Change::Insert(Box::new([i as u8])))
+            );
+        }
+        assert_eq!(map.read_cache.as_ref().unwrap().lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn retry_policy_retries_io_errors_within_budget() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        };
+        let io_error = || {
+            TransactionError::Storage(sled::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "simulated contention",
+            )))
+        };
+
+        let mut attempts = 0;
+        let result: Result<_, TransactionError> = policy.run(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(io_error())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Gives up with the last error once the budget is spent.
+        let mut attempts = 0;
+        let result: Result<(), TransactionError> = policy.run(|| {
+            attempts += 1;
+            Err(io_error())
+        });
+        assert!(matches!(result, Err(TransactionError::Storage(_))));
+        assert_eq!(attempts, 3);
+
+        // Other I/O errors are not transient.
+        let mut attempts = 0;
+        let result: Result<(), TransactionError> = policy.run(|| {
+            attempts += 1;
+            Err(TransactionError::Storage(sled::Error::Io(
+                std::io::ErrorKind::PermissionDenied.into(),
+            )))
+        });
+        assert!(matches!(result, Err(TransactionError::Storage(_))));
+        assert_eq!(attempts, 1);
+
+        // Aborts are never retried.
+        let mut attempts = 0;
+        let result: Result<(), _> = policy.run(|| {
+            attempts += 1;
+            Err(TransactionError::Abort(AbortReason::NoPathExists))
+        });
+        assert_eq!(
+            result,
+            Err(TransactionError::Abort(AbortReason::NoPathExists))
+        );
+        assert_eq!(attempts, 1);
+
+        // The default policy makes a single attempt.
+        let mut attempts = 0;
+        let result: Result<(), TransactionError> = RetryPolicy::default().run(|| {
+            attempts += 1;
+            Err(io_error())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn coordinate_reads_and_writes_agree_with_keys() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+
+        let mut map3 = GridDb::<DbKey3i32>::open(&db, "map3").unwrap();
+        let coords3 = IVec3::new(-5, 2, 7);
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change_at(2, coords3, Change::Insert(Box::new([3])));
+        map3.write_working_version(encoder.encode()).unwrap();
+        assert_eq!(
+            map3.read_working_version_owned(DbKey3i32::new(2, coords3.into()))
+                .unwrap(),
+            Some(Change::Insert(Box::new([3])))
+        );
+        assert_eq!(
+            map3.read_at_coords(2, coords3).unwrap(),
+            Some(Change::Insert(Box::new([3])))
+        );
+        assert_eq!(map3.read_at_coords(1, coords3).unwrap(), None);
+
+        let mut map2 = GridDb::<DbKey2i32>::open(&db, "map2").unwrap();
+        let coords2 = IVec2::new(4, -9);
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(
+            DbKey2i32::new(1, coords2.into()),
+            Change::Insert(Box::new([2])),
+        );
+        map2.write_working_version(encoder.encode()).unwrap();
+        assert_eq!(
+            map2.read_at_coords(1, coords2).unwrap(),
+            Some(Change::Insert(Box::new([2])))
+        );
+        assert_eq!(map2.read_at_coords(1, IVec2::new(4, 9)).unwrap(), None);
+    }
+
+    #[test]
+    fn replay_from_root_reconstructs_leaf() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2, key3] =
+            [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        let commits = [
+            vec![
+                (key1, Change::Insert(Box::new([1]))),
+                (key2, Change::Insert(Box::new([1]))),
+            ],
+            vec![
+                (key2, Change::Insert(Box::new([2]))),
+                (key3, Change::Insert(Box::new([2]))),
+            ],
+            vec![
+                (key1, Change::Remove),
+                (key3, Change::Insert(Box::new([3]))),
+            ],
+        ];
+        let mut versions = Vec::new();
+        for changes in commits {
+            let mut encoder = ChangeEncoder::default();
+            encoder.extend(changes);
+            map.write_working_version(encoder.encode()).unwrap();
+            map.commit_working_version().unwrap();
+            versions.push(map.cached_meta().parent_version.unwrap());
+        }
+        let [root, _, leaf] = [versions[0], versions[1], versions[2]];
+        let read_all = |map: &GridDb<DbKey3i32>| {
+            [key1, key2, key3].map(|key| map.read_working_version_owned(key).unwrap())
+        };
+        let leaf_state = read_all(&map);
+
+        map.branch_from_version(root).unwrap();
+        let replayed: Vec<_> = map.replay(leaf).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            replayed.iter().map(|(v, _)| *v).collect::<Vec<_>>(),
+            vec![versions[2], versions[1], root]
+        );
+        // The root is the current parent version, so it has no archived changes.
+        assert!(replayed[2].1.changes.is_empty());
+
+        for (_, changes) in replayed.into_iter().rev() {
+            let mut encoder = ChangeEncoder::default();
+            encoder.extend(changes.changes);
+            map.write_working_version(encoder.encode()).unwrap();
+        }
+        assert_eq!(read_all(&map), leaf_state);
+
+        assert!(matches!(
+            map.replay(Version::new(1000)),
+            Err(TransactionError::Abort(AbortReason::NoPathExistsToRoot))
+        ));
+    }
+
+    #[test]
+    fn read_working_many_preserves_input_order() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        // A scattered selection of keys, in no particular order, across two levels.
+        let keys: Vec<_> = [9, -3, 0, 14, 7, -8, 2, 11, -1, 5, 20, -12]
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| DbKey3i32::new((i % 2) as u8, IVec3::new(x, -x, x / 2).into()))
+            .collect();
+        let mut encoder = ChangeEncoder::default();
+        for (i, &key) in keys.iter().enumerate().filter(|(i, _)| i % 3 != 0) {
+            encoder.add_change(key, Change::Insert(Box::new([i as u8])));
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let values = map.read_working_many(&keys).unwrap();
+        assert_eq!(values.len(), keys.len());
+        for (i, (key, value)) in values.into_iter().enumerate() {
+            assert_eq!(key, keys[i]);
+            let expected = (i % 3 != 0).then(|| Change::Insert(Box::new([i as u8])));
+            assert_eq!(value, expected);
+        }
+    }
+
+    #[test]
+    fn stale_handle_detects_meta_conflict() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map1 = GridDb::open(&db, "mymap").unwrap();
+        let mut map2 = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let encode = |key, value: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([value])));
+            encoder.encode()
+        };
+        map2.write_working_version(encode(key2, 2)).unwrap();
+        map1.write_working_version(encode(key1, 1)).unwrap();
+        map1.commit_working_version().unwrap();
+        assert_eq!(map1.cached_meta().meta_generation, 1);
+
+        let conflict = Err(TransactionError::Abort(AbortReason::MetaConflict));
+        assert_eq!(map2.commit_working_version().map(|_| ()), conflict);
+        assert_eq!(
+            map2.write_working_version(encode(key2, 3)).map(|_| ()),
+            conflict
+        );
+        assert_eq!(
+            map2.write_working_version_no_backup(encode(key2, 3)),
+            conflict
+        );
+        assert_eq!(map2.cached_meta().meta_generation, 0);
+        assert_eq!(
+            map1.read_working_version_owned(key2).unwrap(),
+            Some(Change::Insert(Box::new([2])))
+        );
+
+        // A fresh handle sees the committed metadata.
+        let map3 = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+        assert_eq!(map3.cached_meta(), map1.cached_meta());
+    }
+
+    #[test]
+    fn retry_policy_reloads_meta_after_conflict() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map1 = GridDb::open(&db, "mymap").unwrap();
+        let mut map2 = GridDb::open(&db, "mymap").unwrap();
+        map2.set_retry_policy(RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::ZERO,
+        });
+
+        let [key1, key2] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let encode = |key, value: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([value])));
+            encoder.encode()
+        };
+        map1.write_working_version(encode(key1, 1)).unwrap();
+        let v1 = map1.commit_working_version().unwrap().unwrap();
+
+        // The stale write is retried on top of the metadata that map1 committed.
+        map2.write_working_version(encode(key2, 2)).unwrap();
+        assert_eq!(map2.cached_meta(), map1.cached_meta());
+        let v2 = map2.commit_working_version().unwrap().unwrap();
+        let node = get_version_node(&map2.version_graph_tree, v2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(node.parent_version, Some(v1));
+
+        // Now map1 is stale, and without retries the conflict reaches the caller.
+        assert_eq!(
+            map1.write_working_version(encode(key1, 3)).map(|_| ()),
+            Err(TransactionError::Abort(AbortReason::MetaConflict))
+        );
+    }
+
+    #[test]
+    fn dashed_map_names_keep_separate_trees() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let names = ["region-5", "region-5-backup", "region", "region-version"];
+        for (i, name) in names.into_iter().enumerate() {
+            let mut map = GridDb::open(&db, name).unwrap();
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([i as u8])));
+            map.write_working_version(encoder.encode()).unwrap();
+            map.commit_working_version().unwrap();
+        }
+        for (i, name) in names.into_iter().enumerate() {
+            let map = GridDb::<DbKey3i32>::open(&db, name).unwrap();
+            assert_eq!(
+                map.read_working_version_owned(key).unwrap(),
+                Some(Change::Insert(Box::new([i as u8])))
+            );
+            assert_eq!(map.stats().unwrap().working_entry_count, 1);
+        }
+
+        // Names without dashes still open the same trees as before.
+        assert!(db.tree_names().iter().any(|n| n == b"region-working"));
+        assert!(db.tree_names().iter().any(|n| n == b"region--5-working"));
+    }
+
+    #[test]
+    fn migrate_legacy_tree_names_moves_dashed_maps() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let mut map = GridDb::open(&db, "source").unwrap();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key, Change::Insert(Box::new([5])));
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap();
+        drop(map);
+
+        // Copy the trees to the names that a map called "region-5" used to have.
+        for suffix in TREE_SUFFIXES {
+            let source = db.open_tree(tree_name("source", suffix)).unwrap();
+            let legacy = db.open_tree(format!("region-5-{}", suffix)).unwrap();
+            for iter_result in source.iter() {
+                let (k, v) = iter_result.unwrap();
+                legacy.insert(k, v).unwrap();
+            }
+        }
+
+        assert_eq!(
+            GridDb::<DbKey3i32>::migrate_legacy_tree_names(&db, "region-6").unwrap(),
+            0
+        );
+        // Opening the map migrates its trees.
+        let map = GridDb::<DbKey3i32>::open(&db, "region-5").unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([5])))
+        );
+        assert!(map.cached_meta().parent_version.is_some());
+        let tree_names = db.tree_names();
+        for suffix in TREE_SUFFIXES {
+            let legacy_name = format!("region-5-{}", suffix);
+            assert!(!tree_names.iter().any(|n| n == legacy_name.as_bytes()));
+        }
+        drop(map);
+        assert_eq!(
+            GridDb::<DbKey3i32>::migrate_legacy_tree_names(&db, "region-5").unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn write_and_commit_reports() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [key1, key2, key3, key4] = [IVec3::ZERO, IVec3::X, IVec3::Y, IVec3::new(0, 0, 1)]
+            .map(|p| DbKey3i32::new(0, p.into()));
+        assert_eq!(map.commit_working_version().unwrap(), None);
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Insert(Box::new([1])));
+        encoder.add_change(key2, Change::Insert(Box::new([1])));
+        assert_eq!(
+            map.write_working_version(encoder.encode()).unwrap(),
+            WriteReport {
+                inserted: 2,
+                removed: 0,
+                newly_backed_up: 2,
+            }
+        );
+        let v0 = map.cached_meta().working_version;
+        assert_eq!(map.commit_working_version().unwrap(), Some(v0));
+        assert_eq!(map.cached_meta().parent_version, Some(v0));
+
+        // Overwrite an existing key, insert an absent key, and remove an existing and an absent key.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Insert(Box::new([2])));
+        encoder.add_change(key3, Change::Insert(Box::new([2])));
+        encoder.add_change(key2, Change::Remove);
+        encoder.add_change(key4, Change::Remove);
+        assert_eq!(
+            map.write_working_version(encoder.encode()).unwrap(),
+            WriteReport {
+                inserted: 2,
+                removed: 2,
+                newly_backed_up: 4,
+            }
+        );
+
+        // Keys that were already backed up in this working version aren't backed up again.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Insert(Box::new([3])));
+        encoder.add_change(key3, Change::Remove);
+        assert_eq!(
+            map.write_working_version(encoder.encode()).unwrap(),
+            WriteReport {
+                inserted: 1,
+                removed: 1,
+                newly_backed_up: 0,
+            }
+        );
+        let v1 = map.cached_meta().working_version;
+        assert_eq!(map.commit_working_version().unwrap(), Some(v1));
+    }
+
+    #[test]
+    fn streamed_insert_reads_back_like_regular_insert() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [regular_key, streamed_key] =
+            [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let payload: Vec<u8> = (0..(3 << 20) + 1).map(|i| (i % 251) as u8).collect();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(regular_key, Change::Insert(payload.clone().into()));
+        encoder
+            .add_change_streaming(streamed_key, payload.as_slice(), payload.len())
+            .unwrap();
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let streamed = map.read_working_version_owned(streamed_key).unwrap();
+        assert_eq!(
+            streamed,
+            map.read_working_version_owned(regular_key).unwrap()
+        );
+        assert_eq!(
+            streamed.unwrap().unwrap_insert().as_ref(),
+            payload.as_slice()
+        );
+    }
+
+    #[test]
+    fn snapshot_extent_reads_region_in_morton_order() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let points = |min: i32, max: i32| {
+            (min..max).flat_map(move |z| {
+                (min..max).flat_map(move |y| (min..max).map(move |x| IVec3::new(x, y, z)))
+            })
+        };
+        let payload = |p: IVec3| vec![(p.x + 10) as u8; 64].into_boxed_slice();
+
+        // Fill a 4x4x4 region at level 1, plus the same coordinates on level 0.
+        let mut encoder = ChangeEncoder::with_compression(Compression::Lz4);
+        for p in points(-2, 2) {
+            encoder.add_change(DbKey3i32::new(1, p.into()), Change::Insert(payload(p)));
+        }
+        encoder.add_change(
+            DbKey3i32::new(0, IVec3::ZERO.into()),
+            Change::Insert(Box::new([0])),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let query = Extent::from_min_and_shape(IVec3::splat(-1), IVec3::splat(2));
+        let snapshot = map.snapshot_extent(1, query).unwrap();
+        assert_eq!(snapshot.len(), 8);
+        for p in points(-1, 1) {
+            assert_eq!(snapshot[&DbKey3i32::new(1, p.into())], payload(p));
+        }
+        let keys: Vec<_> = snapshot.keys().map(|key| key.as_sled_key()).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn read_fails_on_corrupted_checksummed_value() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let [checked_key, plain_key] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let payload: Box<[u8]> = vec![7; 64].into();
+        let mut encoder = ChangeEncoder::with_compression(Compression::Lz4).with_checksums(true);
+        encoder.add_change(checked_key, Change::Insert(payload.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(plain_key, Change::Insert(payload.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        for key in [checked_key, plain_key] {
+            assert_eq!(
+                map.read_working_version_owned(key).unwrap(),
+                Some(Change::Insert(payload.clone()))
+            );
+        }
+
+        // Flip a bit of the stored payload, past the checksum header.
+        let sled_key = IVec::from(checked_key.as_sled_key().as_ref());
+        let mut bytes = map.working_tree.get(&sled_key).unwrap().unwrap().to_vec();
+        bytes[12] ^= 1;
+        map.working_tree.insert(&sled_key, bytes).unwrap();
+
+        let err = map.read_working_version(checked_key).unwrap_err();
+        match err {
+            sled::Error::Io(e) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+                assert!(e.into_inner().unwrap().is::<crate::ChecksumMismatch>());
+            }
+            other => panic!("Expected I/O error, got {:?}", other),
+        }
+        assert!(map.read_working_version(plain_key).is_ok());
+
+        // Every read of the corrupt value reports it the same way.
+        let is_invalid_data = |e: sled::Error| match e {
+            sled::Error::Io(e) => e.kind() == std::io::ErrorKind::InvalidData,
+            _ => false,
+        };
+        let extent = Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(2));
+        assert!(is_invalid_data(map.snapshot_extent(0, extent).unwrap_err()));
+        let mut cursor = map.extent_cursor(0, extent);
+        assert!(is_invalid_data(cursor.next_batch(2).unwrap_err()));
+        let mut copy = GridDb::open(&db, "copy").unwrap();
+        match copy.copy_extent_from(&map, 0, extent, false) {
+            Err(TransactionError::Storage(e)) => assert!(is_invalid_data(e)),
+            other => panic!("Expected storage error, got {:?}", other),
+        }
+    }
+
+    /// Writes a single value and commits it, returning the committed version.
+    fn commit_byte(map: &mut GridDb<DbKey3i32>, byte: u8) -> Version {
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(
+            DbKey3i32::new(0, IVec3::ZERO.into()),
+            Change::Insert(Box::new([byte])),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap().unwrap()
+    }
+
+    #[test]
+    fn common_ancestor_of_related_versions() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let root = commit_byte(&mut map, 0);
+        let left = commit_byte(&mut map, 1);
+        let left_child = commit_byte(&mut map, 2);
+        map.branch_from_version(root).unwrap();
+        let right = commit_byte(&mut map, 3);
+
+        // Siblings.
+        assert_eq!(map.common_ancestor(left, right).unwrap(), Some(root));
+        assert_eq!(map.common_ancestor(left_child, right).unwrap(), Some(root));
+        // Ancestor and descendant, in either order.
+        assert_eq!(map.common_ancestor(root, left_child).unwrap(), Some(root));
+        assert_eq!(map.common_ancestor(left_child, left).unwrap(), Some(left));
+        assert_eq!(map.common_ancestor(right, right).unwrap(), Some(right));
+    }
+
+    #[test]
+    fn common_ancestor_of_disjoint_roots() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let root = commit_byte(&mut map, 0);
+        let child = commit_byte(&mut map, 1);
+        let [other_root, other_child] = [1000, 1001].map(Version::new);
+        let result: Result<_, TransactionError<AbortReason>> =
+            map.version_graph_tree.transaction(|txn| {
+                link_version(txn, other_root, VersionNode::new(None))?;
+                link_version(txn, other_child, VersionNode::new(Some(other_root)))?;
+                Ok(())
+            });
+        result.unwrap();
+
+        assert_eq!(map.common_ancestor(child, other_child).unwrap(), None);
+        assert_eq!(map.common_ancestor(root, other_root).unwrap(), None);
+        assert_eq!(
+            map.common_ancestor(child, Version::new(2000)),
+            Err(TransactionError::Abort(AbortReason::NoPathExistsToRoot))
+        );
+    }
+
+    #[test]
+    fn compact_changes_preserves_archives() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let keys = [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        for (i, &key) in keys.iter().enumerate() {
+            let mut encoder = ChangeEncoder::with_compression(Compression::Lz4);
+            encoder.add_change(key, Change::Insert(vec![i as u8; 256].into()));
+            encoder.add_change(keys[(i + 1) % keys.len()], Change::Remove);
+            map.write_working_version(encoder.encode()).unwrap();
+            map.commit_working_version().unwrap();
+        }
+        let read_all = |map: &GridDb<DbKey3i32>| -> Vec<(Version, VersionChanges<DbKey3i32>)> {
+            map.version_change_tree
+                .iter()
+                .map(|entry| {
+                    let (key_bytes, bytes) = entry.unwrap();
+                    let changes = unsafe { ArchivedIVec::<VersionChanges<_>>::new(bytes) };
+                    (Version::from_sled_key(&key_bytes), changes.deserialize())
+                })
+                .collect()
+        };
+        let before = read_all(&map);
+        // Every commit but the last archived the version before it.
+        let archive_count = keys.len() - 1;
+        assert_eq!(before.len(), archive_count);
+
+        // Freshly written archives are already as small as they get.
+        let report = map.compact_changes().unwrap();
+        assert_eq!(report.entries_scanned, archive_count);
+        assert_eq!(report.entries_rewritten, 0);
+
+        // Padding at the front keeps the archive valid, since it's read from the end.
+        let (key_bytes, bytes) = map.version_change_tree.first().unwrap().unwrap();
+        let mut padded = vec![0; 16];
+        padded.extend_from_slice(&bytes);
+        map.version_change_tree.insert(key_bytes, padded).unwrap();
+
+        let report = map.compact_changes().unwrap();
+        assert_eq!(
+            report,
+            CompactionReport {
+                entries_scanned: archive_count,
+                entries_rewritten: 1,
+                bytes_saved: 16,
+            }
+        );
+        assert_eq!(read_all(&map), before);
+        assert!(map.validate_all_archives().unwrap().is_empty());
+
+        // Reverting still restores the same values.
+        let first = before[0].0;
+        map.branch_from_version(first).unwrap();
+        assert_eq!(
+            map.read_working_version_owned(keys[0]).unwrap(),
+            Some(Change::Insert(vec![0; 256].into()))
+        );
+    }
+
+    #[test]
+    fn compact_changes_compresses_uncompressed_archives() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let keys = [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        for (i, &key) in keys.iter().enumerate() {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(vec![i as u8; 256].into()));
+            // Too small to shrink when compressed.
+            encoder.add_change(keys[(i + 1) % keys.len()], Change::Insert([i as u8].into()));
+            map.write_working_version(encoder.encode()).unwrap();
+            map.commit_working_version().unwrap();
+        }
+        let read_all = |map: &GridDb<DbKey3i32>| -> Vec<(Version, VersionChanges<DbKey3i32>)> {
+            map.version_change_tree
+                .iter()
+                .map(|entry| {
+                    let (key_bytes, bytes) = entry.unwrap();
+                    let changes = unsafe { ArchivedIVec::<VersionChanges<_>>::new(bytes) };
+                    (Version::from_sled_key(&key_bytes), changes.deserialize())
+                })
+                .collect()
+        };
+        let decompress_all = |archives: Vec<(Version, VersionChanges<DbKey3i32>)>| {
+            archives
+                .into_iter()
+                .map(|(version, changes)| {
+                    let changes = changes
+                        .changes
+                        .into_iter()
+                        .map(|(key, change)| (key, change.decompress().unwrap()))
+                        .collect();
+                    (version, VersionChanges::new(changes))
+                })
+                .collect::<Vec<_>>()
+        };
+        let before = read_all(&map);
+        let size_before = map
+            .version_change_tree
+            .iter()
+            .map(|e| e.unwrap().1.len())
+            .sum::<usize>();
+        drop(map);
+
+        // Nothing to gain without compression.
+        let map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+        assert_eq!(map.compact_changes().unwrap().entries_rewritten, 0);
+        drop(map);
+
+        let map = GridDbBuilder::<DbKey3i32>::default()
+            .compression(Compression::Lz4)
+            .open(&db, "mymap")
+            .unwrap();
+        let report = map.compact_changes().unwrap();
+        assert_eq!(report.entries_scanned, before.len());
+        assert_eq!(report.entries_rewritten, before.len());
+        let size_after = map
+            .version_change_tree
+            .iter()
+            .map(|e| e.unwrap().1.len())
+            .sum::<usize>();
+        assert_eq!(report.bytes_saved, (size_before - size_after) as u64);
+
+        let after = read_all(&map);
+        for (_, changes) in &after {
+            let compressed = changes
+                .changes
+                .values()
+                .filter(|c| matches!(c, Change::CompressedInsert(_)));
+            assert_eq!(compressed.count(), 1);
+        }
+        assert_eq!(decompress_all(after), before);
+        assert!(map.validate_all_archives().unwrap().is_empty());
+
+        // Compacting again finds nothing left to shrink.
+        assert_eq!(map.compact_changes().unwrap().entries_rewritten, 0);
+    }
+
+    #[test]
+    fn extent_cursor_batches_match_full_read() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        // Fill a 6x6x6 region, so the query's Morton range also holds keys outside of the query.
+        let mut encoder = ChangeEncoder::default();
+        for z in -3..3 {
+            for y in -3..3 {
+                for x in -3..3 {
+                    let p = IVec3::new(x, y, z);
+                    let payload = Box::new([(x + 3) as u8, (y + 3) as u8, (z + 3) as u8]);
+                    encoder.add_change(DbKey3i32::new(1, p.into()), Change::Insert(payload));
+                }
+            }
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let query = Extent::from_min_and_shape(IVec3::new(-2, -1, -2), IVec3::new(3, 3, 2));
+        let full: Vec<_> = map
+            .snapshot_extent(1, query)
+            .unwrap()
+            .into_iter()
+            .map(|(key, payload)| (key, Change::Insert(payload)))
+            .collect();
+        assert_eq!(full.len(), 18);
+
+        let mut cursor = map.extent_cursor(1, query);
+        let mut paginated = Vec::new();
+        while !cursor.is_exhausted() {
+            let batch = cursor.next_batch(4).unwrap();
+            assert!(batch.len() <= 4);
+            paginated.extend(batch);
+        }
+        assert_eq!(paginated, full);
+        assert!(cursor.next_batch(4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn open_with_seed_commits_root_version() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let [key0, key1] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let seed = |byte: u8| {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key0, Change::Insert(Box::new([byte])));
+            encoder.add_change(key1, Change::Insert(Box::new([byte + 1])));
+            encoder.encode()
+        };
+
+        let root = {
+            let map = GridDb::<DbKey3i32>::open_with_seed(&db, "mymap", seed(1)).unwrap();
+            let meta = map.cached_meta();
+            assert_eq!(meta.grandparent_version, None);
+            meta.parent_version.unwrap()
+        };
+
+        // Reopening with another seed leaves the committed root alone.
+        let mut map = GridDb::open_with_seed(&db, "mymap", seed(5)).unwrap();
+        assert_eq!(map.cached_meta().parent_version, Some(root));
+        assert_eq!(map.graph_stats().unwrap().root_count, 1);
+        let read = |map: &GridDb<DbKey3i32>, key| map.read_working_version_owned(key).unwrap();
+        assert_eq!(read(&map, key0), Some(Change::Insert(Box::new([1]))));
+        assert_eq!(read(&map, key1), Some(Change::Insert(Box::new([2]))));
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key0, Change::Remove);
+        encoder.add_change(key1, Change::Insert(Box::new([9])));
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap();
+
+        map.branch_from_version(root).unwrap();
+        assert_eq!(read(&map, key0), Some(Change::Insert(Box::new([1]))));
+        assert_eq!(read(&map, key1), Some(Change::Insert(Box::new([2]))));
+    }
+
+    #[test]
+    fn builder_configures_cache_compression_and_retries() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        };
+        let mut map = GridDbBuilder::default()
+            .auto_flush(true)
+            .read_cache(8)
+            .retry_policy(policy)
+            .compression(Compression::Lz4)
+            .open(&db, "mymap")
+            .unwrap();
+        assert!(map.auto_flush);
+        assert_eq!(map.retry_policy, policy);
+
+        let parent = DbKey3i32::new(1, IVec3::ZERO.into());
+        map.write_downsampled(parent, |_| Some(vec![7; 256].into()))
+            .unwrap();
+        let stored = map
+            .working_tree
+            .get(parent.as_sled_key().as_ref())
+            .unwrap()
+            .unwrap();
+        match unsafe { ArchivedChangeIVec::new(stored) }.deserialize() {
+            Change::CompressedInsert(bytes) => assert_eq!(bytes[0], Compression::Lz4.tag()),
+            other => panic!("Expected compressed insert, got {:?}", other),
+        }
+
+        assert_eq!(
+            map.read_working_version_owned(parent).unwrap(),
+            Some(Change::Insert(vec![7; 256].into()))
+        );
+        assert_eq!(map.read_cache.as_ref().unwrap().lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn builder_compression_applies_to_written_changes() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDbBuilder::<DbKey3i32>::default()
+            .compression(Compression::Lz4)
+            .open(&db, "mymap")
+            .unwrap();
+
+        let [key1, key2] = [IVec3::ZERO, IVec3::X].map(|p| DbKey3i32::new(0, p.into()));
+        let checksummed = Change::Insert(vec![3; 256].into()).with_checksum();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(key1, Change::Insert(vec![7; 256].into()));
+        encoder.add_change(key2, checksummed.clone());
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let stored = |key: DbKey3i32| {
+            let bytes = map
+                .working_tree
+                .get(key.as_sled_key().as_ref())
+                .unwrap()
+                .unwrap();
+            unsafe { ArchivedChangeIVec::new(bytes) }.deserialize()
+        };
+        match stored(key1) {
+            Change::CompressedInsert(bytes) => assert_eq!(bytes[0], Compression::Lz4.tag()),
+            other => panic!("Expected compressed insert, got {:?}", other),
+        }
+        // Values that the caller already encoded some other way are written as they are.
+        assert_eq!(stored(key2), checksummed);
+
+        assert_eq!(
+            map.read_working_version_owned(key1).unwrap(),
+            Some(Change::Insert(vec![7; 256].into()))
+        );
+    }
+
+    #[test]
+    fn builder_seeds_root_version() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let mut seed = ChangeEncoder::default();
+        seed.add_change(key, Change::Insert(Box::new([1])));
+        let mut map = GridDbBuilder::default()
+            .scratch_capacity(1 << 16)
+            .seed(seed.encode())
+            .open(&db, "mymap")
+            .unwrap();
+        let root = map.cached_meta().parent_version.unwrap();
+        assert!(map.read_cache.is_none());
+
+        let mut encoder = map.encoder();
+        encoder.add_change(key, Change::Insert(Box::new([2])));
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap();
+
+        map.branch_from_version(root).unwrap();
+        assert_eq!(
+            map.read_working_version_owned(key).unwrap(),
+            Some(Change::Insert(Box::new([1])))
+        );
+    }
+
+    #[test]
+    fn write_presorted_matches_encoder() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut presorted = GridDb::open(&db, "presorted").unwrap();
+        let mut encoded = GridDb::open(&db, "encoded").unwrap();
+
+        let mut changes: Vec<_> = (0..2)
+            .flat_map(|level| {
+                (-4..4).flat_map(move |y| {
+                    (-4..4).map(move |x| {
+                        let key = DbKey2i32::new(level, IVec2::new(x, y).into());
+                        (key, Change::Insert(Box::new([level, x as u8, y as u8])))
+                    })
+                })
+            })
+            .collect();
+        changes.sort_by_key(|(key, _)| key.as_sled_key());
+
+        let report = presorted.write_presorted(changes.iter().cloned()).unwrap();
+        let mut encoder = ChangeEncoder::default();
+        encoder.extend(changes.iter().cloned());
+        assert_eq!(
+            report,
+            encoded.write_working_version(encoder.encode()).unwrap()
+        );
+
+        let dump = |map: &GridDb<DbKey2i32>| -> Vec<_> {
+            map.working_tree.iter().map(Result::unwrap).collect()
+        };
+        assert_eq!(dump(&presorted), dump(&encoded));
+        assert_eq!(presorted.working_len(), changes.len());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "keys must be strictly increasing")]
+    fn write_presorted_rejects_unsorted_keys() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+        let [a, b] = [IVec2::ZERO, IVec2::ONE].map(|p| DbKey2i32::new(0, p.into()));
+        let _ = map.write_presorted(
+            [
+                (b, Change::Insert(Box::new([1]))),
+                (a, Change::Insert(Box::new([0]))),
+            ]
+            .into_iter(),
+        );
+    }
+
+    #[test]
+    fn corrupt_compressed_values_fail_reads() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let unknown_tag = Change::CompressedInsert(Box::new([7, 1, 2, 3]));
+        map.working_tree
+            .insert(key.as_sled_key(), unknown_tag.serialize().as_ref())
+            .unwrap();
+        match map.read_working_version_owned(key) {
+            Err(sled::Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            other => panic!("Expected invalid data, got {:?}", other),
+        }
+        let extent = Extent::from_min_and_shape(IVec3::ZERO, IVec3::ONE);
+        assert!(map.extent_cursor(0, extent).next_batch(1).is_err());
+    }
+
+    #[test]
+    fn find_duplicate_value_groups() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let shared_keys = [IVec3::ZERO, IVec3::X, IVec3::Y].map(|p| DbKey3i32::new(0, p.into()));
+        let unique_key = DbKey3i32::new(0, IVec3::Z.into());
+        let mut encoder = ChangeEncoder::default();
+        for key in shared_keys {
+            encoder.add_change(key, Change::Insert(Box::new([1, 2, 3])));
+        }
+        encoder.add_change(unique_key, Change::Insert(Box::new([4])));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let groups = map.duplicate_value_groups().unwrap();
+        assert_eq!(groups.len(), 1);
+        let (_hash, mut keys) = groups.into_iter().next().unwrap();
+        keys.sort();
+        let mut expected_keys = shared_keys.to_vec();
+        expected_keys.sort();
+        assert_eq!(keys, expected_keys);
+    }
+
+    #[test]
+    fn watch_extent_only_delivers_matching_writes() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let watcher = map.watch_extent(1, Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(4)));
+        let handle = std::thread::spawn(move || watcher.take(1).collect::<Result<Vec<_>, _>>());
+
+        let outside_key = DbKey3i32::new(1, IVec3::splat(5).into());
+        let inside_key = DbKey3i32::new(1, IVec3::ONE.into());
+        for key in [outside_key, inside_key] {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([0])));
+            map.write_working_version(encoder.encode()).unwrap();
+        }
+
+        assert_eq!(
+            handle.join().unwrap().unwrap(),
+            vec![(inside_key, Change::Insert(Box::new([0])))]
+        );
+    }
+
+    #[test]
+    fn watch_extent_verifies_checksums() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+
+        let watcher = map.watch_extent(0, Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(4)));
+        let handle = std::thread::spawn(move || watcher.take(1).collect::<Vec<_>>());
+
+        let mut bytes = Change::Insert(Box::new([1, 2, 3]))
+            .with_checksum()
+            .serialize()
+            .to_vec();
+        // Flip a byte of the checksummed data after the tag and checksum.
+        bytes[10] ^= 1;
+        let key = DbKey3i32::new(0, IVec3::ONE.into());
+        map.working_tree
+            .insert(key.as_sled_key().as_ref(), bytes)
+            .unwrap();
+
+        let events = handle.join().unwrap();
+        assert!(matches!(
+            &events[..],
+            [Err(sled::Error::Io(e))] if e.kind() == std::io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[test]
+    fn flush_makes_writes_durable() {
+        let path = std::env::temp_dir().join(format!("grid-db-flush-{}", std::process::id()));
+        let chunk_key = DbKey3i32::new(1, IVec3::ZERO.into());
+        {
+            let db = sled::open(&path).unwrap();
+            let mut map = GridDb::open(&db, "mymap").unwrap();
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(chunk_key, Change::Insert(Box::new([0])));
+            map.write_working_version(encoder.encode()).unwrap();
+            map.commit_working_version().unwrap();
+            map.flush().unwrap();
+        }
+        {
+            let db = sled::open(&path).unwrap();
+            let map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+            assert_eq!(map.cached_meta().parent_version, Some(Version::new(0)));
+            assert_eq!(
+                map.read_working_version(chunk_key)
+                    .unwrap()
+                    .map(|c| c.deserialize()),
+                Some(Change::Insert(Box::new([0])))
+            );
+        }
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn auto_flush_makes_commits_durable() {
+        let path = std::env::temp_dir().join(format!("grid-db-auto-flush-{}", std::process::id()));
+        let chunk_key = DbKey3i32::new(1, IVec3::ZERO.into());
+        let committed_version;
+        {
+            let db = sled::open(&path).unwrap();
+            let mut map = GridDbBuilder::<DbKey3i32>::default()
+                .auto_flush(true)
+                .open(&db, "mymap")
+                .unwrap();
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(chunk_key, Change::Insert(Box::new([0])));
+            map.write_working_version(encoder.encode()).unwrap();
+            committed_version = map.cached_meta().working_version;
+            map.commit_working_version().unwrap();
+        }
+        {
+            let db = sled::open(&path).unwrap();
+            let map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+            assert_eq!(map.cached_meta().parent_version, Some(committed_version));
+            assert!(map.contains_version(committed_version).unwrap());
+            assert_eq!(
+                map.read_working_version_owned(chunk_key).unwrap(),
+                Some(Change::Insert(Box::new([0])))
+            );
+        }
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn read_or_default_falls_back_for_absent_keys() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let present_key = DbKey3i32::new(1, IVec3::ZERO.into());
+        let absent_key = DbKey3i32::new(1, IVec3::ONE.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(present_key, Change::Insert(Box::new([1, 2])));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        assert_eq!(&*map.read_or_default(present_key, &[0]).unwrap(), &[1, 2]);
+        assert_eq!(&*map.read_or_default(absent_key, &[0]).unwrap(), &[0]);
+    }
+
+    #[test]
+    fn commit_empty_working_version_does_nothing() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::<DbKey3i32>::open(&db, "mymap").unwrap();
+
+        assert_eq!(
+            map.cached_meta(),
+            &GridDbMetadata {
+                grandparent_version: None,
+                parent_version: None,
+                working_version: Version::new(0),
+                meta_generation: 0,
+            }
+        );
+
+        map.commit_working_version().unwrap();
+
+        assert_eq!(
+            map.cached_meta(),
+            &GridDbMetadata {
+                grandparent_version: None,
+                parent_version: None,
+                working_version: Version::new(0),
+                meta_generation: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn commit_multiple_versions_with_changes_and_branch() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let chunk_key1 = DbKey3i32::new(1, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key1, Change::Insert(Box::new([0])));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        // Undo the previous change.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key1, Change::Remove);
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let v1 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        assert_eq!(
+            map.cached_meta(),
+            &GridDbMetadata {
+                working_version: Version::new(2),
+                parent_version: Some(v1),
+                grandparent_version: Some(v0),
+                meta_generation: 2,
+            }
+        );
+
+        // We removed the entry in this version.
+        assert_eq!(map.read_working_version(chunk_key1).unwrap(), None);
+
+        // But we can bring it back by reverting to v0.
+        map.branch_from_version(v0).unwrap();
+
+        let expected_insert = Ok(Some(unsafe {
+            ArchivedChangeIVec::new(IVec::from(
+                Change::Insert(Box::new([0])).serialize().as_ref(),
+            ))
+        }));
+
+        assert_eq!(map.read_working_version(chunk_key1), expected_insert);
+
+        // Commit changes to the branch.
+        let chunk_key2 = DbKey3i32::new(2, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(chunk_key2, Change::Insert(Box::new([0])));
+        map.write_working_version(encoder.encode()).unwrap();
+        let v2 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        // Branch from a sibling version.
+        map.branch_from_version(v1).unwrap();
+        assert_eq!(map.read_working_version(chunk_key1), Ok(None));
+        assert_eq!(map.read_working_version(chunk_key2).unwrap(), None);
+
+        // And back.
+        map.branch_from_version(v2).unwrap();
+        assert_eq!(map.read_working_version(chunk_key1), expected_insert);
+        assert_eq!(map.read_working_version(chunk_key2), expected_insert);
+    }
+
+    #[test]
+    fn squash_preserves_reverts_across_range() {
+        // Squash with the parent version at either end of the range.
+        for revert_before_squash in [false, true] {
+            let db = sled::Config::default().temporary(true).open().unwrap();
+            let mut map = GridDb::open(&db, "mymap").unwrap();
+
+            let [a, b, c] = [0, 1, 2].map(|i| DbKey3i32::new(0, IVec3::new(i, 0, 0).into()));
+            let insert = |v: u8| Change::Insert(Box::new([v]));
+            let mut versions = Vec::new();
+            for changes in [
+                vec![(a, insert(0))],
+                vec![(a, insert(1)), (b, insert(1))],
+                vec![(b, Change::Remove), (c, insert(2))],
+                vec![(c, insert(3))],
+            ] {
+                let mut encoder = ChangeEncoder::default();
+                for (key, change) in changes {
+                    encoder.add_change(key, change);
+                }
+                map.write_working_version(encoder.encode()).unwrap();
+                versions.push(map.cached_meta().working_version);
+                map.commit_working_version().unwrap();
+            }
+            let [v0, v1, v2, v3] = [versions[0], versions[1], versions[2], versions[3]];
+
+            let snapshot = |map: &GridDb<DbKey3i32>| -> Vec<_> {
+                map.working_tree
+                    .iter()
+                    .map(|entry| entry.unwrap())
+                    .collect()
+            };
+            map.branch_from_version(v0).unwrap();
+            let v0_state = snapshot(&map);
+            map.branch_from_version(v3).unwrap();
+            let v3_state = snapshot(&map);
+            if revert_before_squash {
+                map.branch_from_version(v0).unwrap();
+            }
+
+            assert_eq!(map.squash(v0, v3), Ok(v3));
+            assert_eq!(map.graph_stats().unwrap().node_count, 2);
+            assert_eq!(
+                map.branch_from_version(v1),
+                Err(TransactionError::Abort(AbortReason::NoPathExistsToRoot))
+            );
+            assert!(!map
+                .version_change_tree
+                .contains_key(v2.into_sled_key())
+                .unwrap());
+
+            map.branch_from_version(v3).unwrap();
+            assert_eq!(snapshot(&map), v3_state);
+            map.branch_from_version(v0).unwrap();
+            assert_eq!(snapshot(&map), v0_state);
+            map.branch_from_version(v3).unwrap();
+            assert_eq!(snapshot(&map), v3_state);
+        }
+    }
+
+    #[test]
+    fn squash_aborts_on_branch_in_range() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut map = GridDb::open(&db, "mymap").unwrap();
+
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let mut versions = Vec::new();
+        for i in 0..3 {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_change(key, Change::Insert(Box::new([i])));
+            map.write_working_version(encoder.encode()).unwrap();
+            versions.push(map.cached_meta().working_version);
+            map.commit_working_version().unwrap();
+        }
+
+        // Branch off of the middle version.
+        map.branch_from_version(versions[1]).unwrap();
+        assert_eq!(
+            map.squash(versions[0], versions[2]),
+            Err(TransactionError::Abort(AbortReason::SquashNotLinear))
+        );
+    }
+}
@@ -1,27 +1,208 @@
-use crate::{Level, NoSharedAllocSerializer};
+use crate::{Level, NoSharedAllocSerializer, DEFAULT_SCRATCH_SIZE};
 
 use core::ops::RangeInclusive;
-use ilattice::glam::{IVec2, IVec3};
-use ilattice::prelude::{Bounded, Extent, Morton2i32, Morton3i32};
-use rkyv::{Archive, Deserialize, Serialize};
+use ilattice::glam::{I64Vec2, I64Vec3, IVec2, IVec3};
+use ilattice::prelude::{Bounded, Extent, Morton2i32, Morton2i64, Morton3i32, Morton3i64};
+use rkyv::{Archive, CheckBytes, Deserialize, Serialize};
 use std::fmt::Debug;
 use std::hash::Hash;
 
 pub trait DbKey:
-    Archive + Clone + Debug + Eq + Hash + Ord + Sized + Serialize<NoSharedAllocSerializer<8192>>
+    Archive
+    + Clone
+    + Debug
+    + Eq
+    + Hash
+    + Ord
+    + Sized
+    + Serialize<NoSharedAllocSerializer<DEFAULT_SCRATCH_SIZE>>
 {
-    type Coords;
+    type Coords: Copy + PartialEq;
     type SledKey: AsRef<[u8]>;
 
     fn as_sled_key(&self) -> Self::SledKey;
     fn from_sled_key(bytes: &[u8]) -> Self;
 
+    /// The key of the node at `coords` on `level`, so callers never need to build the Morton code themselves.
+    fn from_coords(level: Level, coords: Self::Coords) -> Self;
+
+    fn level(&self) -> Level;
+    /// Decodes the coordinates of this node.
+    fn coords(&self) -> Self::Coords;
+
+    /// The key of the node at `level` that covers this node. Each level up halves the coordinates, rounding down, so far
+    /// enough up every coordinate ends up at 0 or -1.
+    ///
+    /// Panics if `level` is finer than `self.level()`.
+    fn ancestor(&self, level: Level) -> Self;
+
+    /// The key of the node at `level + 1` that covers this node. Nodes at [`Level::MAX`] are their own parent.
+    fn parent(&self) -> Self {
+        if self.level() == Level::MAX {
+            return self.clone();
+        }
+        self.ancestor(self.level() + 1)
+    }
+
+    /// The keys of the nodes at `level - 1` covered by this node, in Morton order.
+    ///
+    /// Empty at level 0, or when the child coordinates would not be representable.
+    fn children(&self) -> Vec<Self>;
+
+    /// Offsets from the coordinates of a node to those of its face neighbors at the same level: the nodes sharing a face in
+    /// 3D, an edge in 2D or an endpoint in 1D.
+    const FACE_NEIGHBOR_OFFSETS: &'static [Self::Coords];
+
+    /// The keys of the face neighbors of this node, in the order of [`DbKey::FACE_NEIGHBOR_OFFSETS`]. Neighbors outside of the
+    /// representable coordinate range are omitted.
+    fn face_neighbors(&self) -> Vec<Self>;
+
     fn extent_range(level: u8, extent: Extent<Self::Coords>) -> RangeInclusive<Self>;
+    /// The Morton range of an extent also covers some points outside of the extent, so this is used to filter them.
+    fn extent_contains(extent: &Extent<Self::Coords>, coords: Self::Coords) -> bool;
 
     fn min_key(level: u8) -> Self;
     fn max_key(level: u8) -> Self;
 }
 
+#[derive(
+    Archive, Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug, Eq, Hash, PartialEq, PartialOrd, Ord))]
+pub struct DbKey1i32 {
+    pub level: Level,
+    pub coord: i32,
+}
+
+impl DbKey1i32 {
+    pub fn new(level: Level, coord: i32) -> Self {
+        Self { level, coord }
+    }
+}
+
+impl DbKey for DbKey1i32 {
+    type Coords = i32;
+    type SledKey = [u8; 5];
+
+    const FACE_NEIGHBOR_OFFSETS: &'static [i32] = &[-1, 1];
+
+    /// There is no Morton encoding in 1D, so the coordinate is stored directly.
+    ///
+    /// 5 bytes total per key, 1 for LOD and 4 for the coordinate. The sign bit is flipped so that the big-endian bytes of
+    /// negative coordinates sort before positive ones, matching the [`Ord`] of `i32`.
+    fn as_sled_key(&self) -> Self::SledKey {
+        let mut bytes = [0; 5];
+        bytes[0] = self.level;
+        bytes[1..].copy_from_slice(&((self.coord as u32) ^ SIGN_BIT_32).to_be_bytes());
+        bytes
+    }
+
+    fn from_sled_key(bytes: &[u8]) -> Self {
+        let level = bytes[0];
+        let mut coord_bytes = [0; 4];
+        coord_bytes.copy_from_slice(&bytes[1..]);
+        let coord = (u32::from_be_bytes(coord_bytes) ^ SIGN_BIT_32) as i32;
+        Self::new(level, coord)
+    }
+
+    fn from_coords(level: Level, coords: i32) -> Self {
+        Self::new(level, coords)
+    }
+
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn coords(&self) -> i32 {
+        self.coord
+    }
+
+    fn ancestor(&self, level: Level) -> Self {
+        Self::from_coords(
+            level,
+            self.coords() >> ancestor_shift(self.level, level, i32::BITS) as i32,
+        )
+    }
+
+    fn children(&self) -> Vec<Self> {
+        let min = self.coords() << 1;
+        checked_children(self, [min, min + 1])
+    }
+
+    fn face_neighbors(&self) -> Vec<Self> {
+        checked_neighbors(
+            self,
+            Self::FACE_NEIGHBOR_OFFSETS
+                .iter()
+                .map(|&offset| self.coord.checked_add(offset)),
+        )
+    }
+
+    fn extent_range(level: u8, extent: Extent<i32>) -> RangeInclusive<Self> {
+        let max = extent.minimum + extent.shape - 1;
+        Self::new(level, extent.minimum)..=Self::new(level, max)
+    }
+
+    fn extent_contains(extent: &Extent<i32>, coords: i32) -> bool {
+        coords >= extent.minimum && coords - extent.minimum < extent.shape
+    }
+
+    fn min_key(level: u8) -> Self {
+        Self::new(level, i32::MIN)
+    }
+
+    fn max_key(level: u8) -> Self {
+        Self::new(level, i32::MAX)
+    }
+}
+
+/// Builds the child keys of `parent` from their coordinates, discarding all of them if any child falls outside of the
+/// representable range (and therefore doesn't map back to `parent`).
+fn checked_children<K: DbKey>(
+    parent: &K,
+    child_coords: impl IntoIterator<Item = K::Coords>,
+) -> Vec<K> {
+    if parent.level() == 0 {
+        return Vec::new();
+    }
+    let children: Vec<K> = child_coords
+        .into_iter()
+        .map(|coords| K::from_coords(parent.level() - 1, coords))
+        .collect();
+    if children.iter().all(|child| child.parent() == *parent) {
+        children
+    } else {
+        Vec::new()
+    }
+}
+
+/// Builds the neighbor keys of `key` from their coordinates, which are `None` if they overflowed. Neighbors that would not be
+/// representable by the key are also discarded.
+fn checked_neighbors<K: DbKey>(
+    key: &K,
+    neighbor_coords: impl IntoIterator<Item = Option<K::Coords>>,
+) -> Vec<K> {
+    neighbor_coords
+        .into_iter()
+        .flatten()
+        .map(|coords| (K::from_coords(key.level(), coords), coords))
+        .filter(|(neighbor, coords)| neighbor.coords() == *coords)
+        .map(|(neighbor, _)| neighbor)
+        .collect()
+}
+
+const SIGN_BIT_32: u32 = 1 << 31;
+
+/// The number of bits to shift a coordinate right by to go from level `from` up to level `to`.
+///
+/// Shifting by `coord_bits` or more would overflow, so the shift is clamped to `coord_bits - 1`. That shifts out every bit
+/// but the sign, saturating the coordinate at 0 or -1 as a longer shift would.
+fn ancestor_shift(from: Level, to: Level, coord_bits: u32) -> u32 {
+    assert!(to >= from);
+    u32::from(to - from).min(coord_bits - 1)
+}
+
 #[derive(
     Archive, Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize,
 )]
@@ -41,6 +222,13 @@ impl DbKey for DbKey2i32 {
     type Coords = IVec2;
     type SledKey = [u8; 9];
 
+    const FACE_NEIGHBOR_OFFSETS: &'static [IVec2] = &[
+        IVec2::new(-1, 0),
+        IVec2::new(1, 0),
+        IVec2::new(0, -1),
+        IVec2::new(0, 1),
+    ];
+
     /// We implement this manually (without rkyv) so we have control over the [`Ord`] as interpreted by [`sled`].
     ///
     /// 9 bytes total per key, 1 for LOD and 8 for the morton code.
@@ -59,12 +247,53 @@ impl DbKey for DbKey2i32 {
         Self::new(level, Morton2i32(morton_int))
     }
 
+    fn from_coords(level: Level, coords: IVec2) -> Self {
+        Self::new(level, coords.into())
+    }
+
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn coords(&self) -> IVec2 {
+        IVec2::from(self.morton)
+    }
+
+    fn ancestor(&self, level: Level) -> Self {
+        Self::from_coords(
+            level,
+            self.coords() >> ancestor_shift(self.level, level, i32::BITS) as i32,
+        )
+    }
+
+    fn children(&self) -> Vec<Self> {
+        let min = self.coords() << 1;
+        checked_children(self, (0..4).map(|i| min + IVec2::new(i & 1, (i >> 1) & 1)))
+    }
+
+    fn face_neighbors(&self) -> Vec<Self> {
+        let c = self.coords();
+        checked_neighbors(
+            self,
+            Self::FACE_NEIGHBOR_OFFSETS.iter().map(|&offset| {
+                Some(IVec2::new(
+                    c.x.checked_add(offset.x)?,
+                    c.y.checked_add(offset.y)?,
+                ))
+            }),
+        )
+    }
+
     fn extent_range(level: u8, extent: Extent<IVec2>) -> RangeInclusive<Self> {
         let min_morton = Morton2i32::from(extent.minimum);
         let max_morton = Morton2i32::from(extent.max());
         Self::new(level, min_morton)..=Self::new(level, max_morton)
     }
 
+    fn extent_contains(extent: &Extent<IVec2>, coords: IVec2) -> bool {
+        extent.contains(coords)
+    }
+
     fn min_key(level: u8) -> Self {
         Self::new(level, Morton2i32::from(IVec2::MIN))
     }
@@ -93,6 +322,15 @@ impl DbKey for DbKey3i32 {
     type Coords = IVec3;
     type SledKey = [u8; 13];
 
+    const FACE_NEIGHBOR_OFFSETS: &'static [IVec3] = &[
+        IVec3::new(-1, 0, 0),
+        IVec3::new(1, 0, 0),
+        IVec3::new(0, -1, 0),
+        IVec3::new(0, 1, 0),
+        IVec3::new(0, 0, -1),
+        IVec3::new(0, 0, 1),
+    ];
+
     /// We implement this manually (without rkyv) so we have control over the [`Ord`] as interpreted by [`sled`].
     ///
     /// 13 bytes total per key, 1 for LOD and 12 for the morton code. Although a [`Morton3i32`] uses a u128, it only actually
@@ -113,12 +351,57 @@ impl DbKey for DbKey3i32 {
         Self::new(level, Morton3i32(morton_int))
     }
 
+    fn from_coords(level: Level, coords: IVec3) -> Self {
+        Self::new(level, coords.into())
+    }
+
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn coords(&self) -> IVec3 {
+        IVec3::from(self.morton)
+    }
+
+    fn ancestor(&self, level: Level) -> Self {
+        Self::from_coords(
+            level,
+            self.coords() >> ancestor_shift(self.level, level, i32::BITS) as i32,
+        )
+    }
+
+    fn children(&self) -> Vec<Self> {
+        let min = self.coords() << 1;
+        checked_children(
+            self,
+            (0..8).map(|i| min + IVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1)),
+        )
+    }
+
+    fn face_neighbors(&self) -> Vec<Self> {
+        let c = self.coords();
+        checked_neighbors(
+            self,
+            Self::FACE_NEIGHBOR_OFFSETS.iter().map(|&offset| {
+                Some(IVec3::new(
+                    c.x.checked_add(offset.x)?,
+                    c.y.checked_add(offset.y)?,
+                    c.z.checked_add(offset.z)?,
+                ))
+            }),
+        )
+    }
+
     fn extent_range(level: u8, extent: Extent<IVec3>) -> RangeInclusive<Self> {
         let min_morton = Morton3i32::from(extent.minimum);
         let max_morton = Morton3i32::from(extent.max());
         Self::new(level, min_morton)..=Self::new(level, max_morton)
     }
 
+    fn extent_contains(extent: &Extent<IVec3>, coords: IVec3) -> bool {
+        extent.contains(coords)
+    }
+
     fn min_key(level: u8) -> Self {
         Self::new(level, Morton3i32::from(IVec3::MIN))
     }
@@ -127,3 +410,505 @@ impl DbKey for DbKey3i32 {
         Self::new(level, Morton3i32::from(IVec3::MAX))
     }
 }
+
+#[derive(
+    Archive, Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize,
+)]
+#[archive_attr(derive(Debug, Eq, Hash, PartialEq, PartialOrd, Ord))]
+pub struct DbKey2i64 {
+    pub level: Level,
+    pub morton: Morton2i64,
+}
+
+impl DbKey2i64 {
+    pub fn new(level: Level, morton: Morton2i64) -> Self {
+        Self { level, morton }
+    }
+}
+
+impl DbKey for DbKey2i64 {
+    type Coords = I64Vec2;
+    type SledKey = [u8; 17];
+
+    const FACE_NEIGHBOR_OFFSETS: &'static [I64Vec2] = &[
+        I64Vec2::new(-1, 0),
+        I64Vec2::new(1, 0),
+        I64Vec2::new(0, -1),
+        I64Vec2::new(0, 1),
+    ];
+
+    /// We implement this manually (without rkyv) so we have control over the [`Ord`] as interpreted by [`sled`].
+    ///
+    /// 17 bytes total per key, 1 for LOD and 16 for the morton code. A [`Morton2i64`] interleaves two full 64-bit coordinates,
+    /// so all 128 bits are significant.
+    fn as_sled_key(&self) -> Self::SledKey {
+        let mut bytes = [0; 17];
+        bytes[0] = self.level;
+        bytes[1..].copy_from_slice(&self.morton.0.to_be_bytes());
+        bytes
+    }
+
+    fn from_sled_key(bytes: &[u8]) -> Self {
+        let level = bytes[0];
+        let mut morton_bytes = [0; 16];
+        morton_bytes.copy_from_slice(&bytes[1..]);
+        let morton_int = u128::from_be_bytes(morton_bytes);
+        Self::new(level, Morton2i64(morton_int))
+    }
+
+    fn from_coords(level: Level, coords: I64Vec2) -> Self {
+        Self::new(level, coords.into())
+    }
+
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn coords(&self) -> I64Vec2 {
+        I64Vec2::from(self.morton)
+    }
+
+    fn ancestor(&self, level: Level) -> Self {
+        Self::from_coords(
+            level,
+            self.coords() >> ancestor_shift(self.level, level, i64::BITS) as i64,
+        )
+    }
+
+    fn children(&self) -> Vec<Self> {
+        let min = self.coords() << 1;
+        checked_children(
+            self,
+            (0..4).map(|i| min + I64Vec2::new(i & 1, (i >> 1) & 1)),
+        )
+    }
+
+    fn face_neighbors(&self) -> Vec<Self> {
+        let c = self.coords();
+        checked_neighbors(
+            self,
+            Self::FACE_NEIGHBOR_OFFSETS.iter().map(|&offset| {
+                Some(I64Vec2::new(
+                    c.x.checked_add(offset.x)?,
+                    c.y.checked_add(offset.y)?,
+                ))
+            }),
+        )
+    }
+
+    fn extent_range(level: u8, extent: Extent<I64Vec2>) -> RangeInclusive<Self> {
+        let min_morton = Morton2i64::from(extent.minimum);
+        let max_morton = Morton2i64::from(extent.max());
+        Self::new(level, min_morton)..=Self::new(level, max_morton)
+    }
+
+    fn extent_contains(extent: &Extent<I64Vec2>, coords: I64Vec2) -> bool {
+        extent.contains(coords)
+    }
+
+    fn min_key(level: u8) -> Self {
+        Self::new(level, Morton2i64::from(I64Vec2::MIN))
+    }
+
+    fn max_key(level: u8) -> Self {
+        Self::new(level, Morton2i64::from(I64Vec2::MAX))
+    }
+}
+
+/// A key with 64-bit coordinates in 3D.
+///
+/// Only 42 bits of each coordinate fit in the key, so coordinates are limited to
+/// [`DbKey3i64::MIN_COORD`]`..=`[`DbKey3i64::MAX_COORD`]. Coordinates outside of that range would alias the key of another
+/// node, so [`DbKey::from_coords`] asserts the range in debug builds, and [`DbKey3i64::checked_from_coords`] checks it in all
+/// builds.
+#[derive(
+    Archive, Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize,
+)]
+#[archive_attr(derive(Debug, Eq, Hash, PartialEq, PartialOrd, Ord))]
+pub struct DbKey3i64 {
+    pub level: Level,
+    pub morton: Morton3i64,
+}
+
+impl DbKey3i64 {
+    pub const MIN_COORD: i64 = -(1 << 41);
+    pub const MAX_COORD: i64 = (1 << 41) - 1;
+
+    pub fn new(level: Level, morton: Morton3i64) -> Self {
+        Self { level, morton }
+    }
+
+    /// Same as [`DbKey::from_coords`], but returns `None` if any coordinate is outside of
+    /// [`DbKey3i64::MIN_COORD`]`..=`[`DbKey3i64::MAX_COORD`].
+    pub fn checked_from_coords(level: Level, coords: I64Vec3) -> Option<Self> {
+        Self::coords_in_range(coords).then(|| Self::new(level, coords.into()))
+    }
+
+    fn coords_in_range(coords: I64Vec3) -> bool {
+        let range = Self::MIN_COORD..=Self::MAX_COORD;
+        range.contains(&coords.x) && range.contains(&coords.y) && range.contains(&coords.z)
+    }
+}
+
+/// Three 64-bit coordinates don't fit in a u128, so a [`Morton3i64`] only interleaves the least significant 42 bits of each
+/// coordinate, for 126 significant bits.
+const MORTON3I64_MASK: u128 = (1 << 126) - 1;
+
+impl DbKey for DbKey3i64 {
+    type Coords = I64Vec3;
+    type SledKey = [u8; 17];
+
+    const FACE_NEIGHBOR_OFFSETS: &'static [I64Vec3] = &[
+        I64Vec3::new(-1, 0, 0),
+        I64Vec3::new(1, 0, 0),
+        I64Vec3::new(0, -1, 0),
+        I64Vec3::new(0, 1, 0),
+        I64Vec3::new(0, 0, -1),
+        I64Vec3::new(0, 0, 1),
+    ];
+
+    /// We implement this manually (without rkyv) so we have control over the [`Ord`] as interpreted by [`sled`].
+    ///
+    /// 17 bytes total per key, 1 for LOD and 16 for the morton code. Only the least significant 126 bits of the morton code
+    /// are significant (42 bits per axis, so coordinates are limited to `-2^41..2^41`). The 2 unused high bits are always
+    /// written as zero and masked off when decoding.
+    fn as_sled_key(&self) -> Self::SledKey {
+        let mut bytes = [0; 17];
+        bytes[0] = self.level;
+        bytes[1..].copy_from_slice(&(self.morton.0 & MORTON3I64_MASK).to_be_bytes());
+        bytes
+    }
+
+    fn from_sled_key(bytes: &[u8]) -> Self {
+        let level = bytes[0];
+        let mut morton_bytes = [0; 16];
+        morton_bytes.copy_from_slice(&bytes[1..]);
+        let morton_int = u128::from_be_bytes(morton_bytes) & MORTON3I64_MASK;
+        Self::new(level, Morton3i64(morton_int))
+    }
+
+    fn from_coords(level: Level, coords: I64Vec3) -> Self {
+        debug_assert!(
+            Self::coords_in_range(coords),
+            "{:?} is outside of the representable range of DbKey3i64",
+            coords
+        );
+        Self::new(level, coords.into())
+    }
+
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn coords(&self) -> I64Vec3 {
+        I64Vec3::from(self.morton)
+    }
+
+    fn ancestor(&self, level: Level) -> Self {
+        Self::from_coords(
+            level,
+            self.coords() >> ancestor_shift(self.level, level, i64::BITS) as i64,
+        )
+    }
+
+    fn children(&self) -> Vec<Self> {
+        let min = self.coords() << 1;
+        let max = min + I64Vec3::ONE;
+        if !Self::coords_in_range(min) || !Self::coords_in_range(max) {
+            return Vec::new();
+        }
+        checked_children(
+            self,
+            (0..8).map(|i| min + I64Vec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1)),
+        )
+    }
+
+    fn face_neighbors(&self) -> Vec<Self> {
+        let c = self.coords();
+        checked_neighbors(
+            self,
+            Self::FACE_NEIGHBOR_OFFSETS
+                .iter()
+                .map(|&offset| Some(c + offset).filter(|&n| Self::coords_in_range(n))),
+        )
+    }
+
+    fn extent_range(level: u8, extent: Extent<I64Vec3>) -> RangeInclusive<Self> {
+        let min_morton = Morton3i64::from(extent.minimum);
+        let max_morton = Morton3i64::from(extent.max());
+        Self::new(level, min_morton)..=Self::new(level, max_morton)
+    }
+
+    fn extent_contains(extent: &Extent<I64Vec3>, coords: I64Vec3) -> bool {
+        extent.contains(coords)
+    }
+
+    fn min_key(level: u8) -> Self {
+        Self::new(level, Morton3i64::from(I64Vec3::MIN))
+    }
+
+    fn max_key(level: u8) -> Self {
+        Self::new(level, Morton3i64::from(I64Vec3::MAX))
+    }
+}
+
+// The archived Morton codes from ilattice don't implement CheckBytes, so we vouch for the Morton keys ourselves. Every field is
+// an integer, so any bit pattern is valid.
+
+unsafe impl<C: ?Sized> CheckBytes<C> for ArchivedDbKey2i32 {
+    type Error = core::convert::Infallible;
+
+    unsafe fn check_bytes<'a>(
+        value: *const Self,
+        _context: &mut C,
+    ) -> Result<&'a Self, Self::Error> {
+        Ok(&*value)
+    }
+}
+
+unsafe impl<C: ?Sized> CheckBytes<C> for ArchivedDbKey3i32 {
+    type Error = core::convert::Infallible;
+
+    unsafe fn check_bytes<'a>(
+        value: *const Self,
+        _context: &mut C,
+    ) -> Result<&'a Self, Self::Error> {
+        Ok(&*value)
+    }
+}
+
+unsafe impl<C: ?Sized> CheckBytes<C> for ArchivedDbKey2i64 {
+    type Error = core::convert::Infallible;
+
+    unsafe fn check_bytes<'a>(
+        value: *const Self,
+        _context: &mut C,
+    ) -> Result<&'a Self, Self::Error> {
+        Ok(&*value)
+    }
+}
+
+unsafe impl<C: ?Sized> CheckBytes<C> for ArchivedDbKey3i64 {
+    type Error = core::convert::Infallible;
+
+    unsafe fn check_bytes<'a>(
+        value: *const Self,
+        _context: &mut C,
+    ) -> Result<&'a Self, Self::Error> {
+        Ok(&*value)
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_1d_keys() {
+        for coord in [i32::MIN, -1, 0, 1, i32::MAX] {
+            let key = DbKey1i32::new(3, coord);
+            assert_eq!(DbKey1i32::from_sled_key(key.as_sled_key().as_ref()), key);
+        }
+    }
+
+    #[test]
+    fn sled_order_matches_1d_key_order() {
+        let keys = [
+            DbKey1i32::min_key(0),
+            DbKey1i32::new(0, -1),
+            DbKey1i32::new(0, 0),
+            DbKey1i32::new(0, 1),
+            DbKey1i32::max_key(0),
+            DbKey1i32::min_key(1),
+        ];
+        for (k1, k2) in keys.iter().zip(keys.iter().skip(1)) {
+            assert!(k1 < k2);
+            assert!(k1.as_sled_key() < k2.as_sled_key());
+        }
+    }
+
+    #[test]
+    fn extent_range_1d_endpoints() {
+        let extent = Extent::from_min_and_shape(-2, 5);
+        let range = DbKey1i32::extent_range(2, extent);
+        assert_eq!(*range.start(), DbKey1i32::new(2, -2));
+        assert_eq!(*range.end(), DbKey1i32::new(2, 2));
+        assert!(DbKey1i32::extent_contains(&extent, 2));
+        assert!(!DbKey1i32::extent_contains(&extent, 3));
+        assert!(!DbKey1i32::extent_contains(&extent, -3));
+    }
+
+    #[test]
+    fn round_trip_64_bit_keys() {
+        let far = 1 << 40;
+        let key2 = DbKey2i64::new(1, I64Vec2::new(far, -far).into());
+        assert_eq!(DbKey2i64::from_sled_key(key2.as_sled_key().as_ref()), key2);
+        assert_eq!(key2.coords(), I64Vec2::new(far, -far));
+
+        let far = 1 << 35;
+        let key3 = DbKey3i64::new(1, I64Vec3::new(far, -far, 3).into());
+        assert_eq!(DbKey3i64::from_sled_key(key3.as_sled_key().as_ref()), key3);
+        assert_eq!(key3.coords(), I64Vec3::new(far, -far, 3));
+    }
+
+    #[test]
+    fn extent_range_brackets_64_bit_keys() {
+        let far = 1 << 35;
+        let extent = Extent::from_min_and_shape(I64Vec3::splat(far), I64Vec3::splat(4));
+        let range = DbKey3i64::extent_range(0, extent);
+        let inside = DbKey3i64::new(0, I64Vec3::splat(far + 2).into());
+        let outside = DbKey3i64::new(0, I64Vec3::splat(far + 4).into());
+        assert!(range.contains(&inside));
+        assert!(!range.contains(&outside));
+        assert!(range.start().as_sled_key() <= inside.as_sled_key());
+        assert!(inside.as_sled_key() <= range.end().as_sled_key());
+
+        let extent = Extent::from_min_and_shape(I64Vec2::splat(-far), I64Vec2::splat(4));
+        let range = DbKey2i64::extent_range(0, extent);
+        let inside = DbKey2i64::new(0, I64Vec2::splat(-far + 3).into());
+        assert!(range.contains(&inside));
+        assert!(!range.contains(&DbKey2i64::new(0, I64Vec2::splat(-far - 1).into())));
+    }
+
+    #[test]
+    fn children_map_back_to_parent() {
+        let key2 = DbKey2i32::from_coords(2, IVec2::new(-3, 5));
+        let key3 = DbKey3i32::from_coords(2, IVec3::new(-3, 5, 7));
+        let key3_64 = DbKey3i64::from_coords(2, I64Vec3::new(-3, 5, 1 << 30));
+        assert_children_map_back(&DbKey1i32::new(2, -3), 2);
+        assert_children_map_back(&key2, 4);
+        assert_children_map_back(&DbKey2i64::from_coords(2, I64Vec2::new(-3, 1 << 40)), 4);
+        assert_children_map_back(&key3, 8);
+        assert_children_map_back(&key3_64, 8);
+    }
+
+    fn assert_children_map_back<K: DbKey>(key: &K, num_children: usize) {
+        let children = key.children();
+        assert_eq!(children.len(), num_children);
+        for child in children.iter() {
+            assert_eq!(child.level(), key.level() - 1);
+            assert_eq!(child.parent(), *key);
+        }
+        // Morton ordering is preserved: siblings are contiguous and sorted.
+        for (c1, c2) in children.iter().zip(children.iter().skip(1)) {
+            assert!(c1 < c2);
+        }
+    }
+
+    #[test]
+    fn face_neighbors_in_every_dimension() {
+        let key1 = DbKey1i32::new(1, 5);
+        assert_eq!(
+            key1.face_neighbors(),
+            vec![DbKey1i32::new(1, 4), DbKey1i32::new(1, 6)]
+        );
+
+        let key2 = DbKey2i32::from_coords(1, IVec2::new(-3, 5));
+        let neighbors: Vec<_> = key2.face_neighbors().iter().map(|n| n.coords()).collect();
+        assert_eq!(
+            neighbors,
+            [
+                IVec2::new(-4, 5),
+                IVec2::new(-2, 5),
+                IVec2::new(-3, 4),
+                IVec2::new(-3, 6)
+            ]
+        );
+
+        let key3 = DbKey3i32::from_coords(1, IVec3::new(-3, 5, 7));
+        assert_eq!(key3.face_neighbors().len(), 6);
+        for (neighbor, offset) in key3
+            .face_neighbors()
+            .iter()
+            .zip(DbKey3i32::FACE_NEIGHBOR_OFFSETS)
+        {
+            assert_eq!(neighbor.level(), 1);
+            assert_eq!(neighbor.coords(), key3.coords() + *offset);
+        }
+        assert_eq!(
+            DbKey2i64::from_coords(0, I64Vec2::ZERO)
+                .face_neighbors()
+                .len(),
+            4
+        );
+        assert_eq!(
+            DbKey3i64::from_coords(0, I64Vec3::ZERO)
+                .face_neighbors()
+                .len(),
+            6
+        );
+    }
+
+    #[test]
+    fn face_neighbors_are_omitted_at_extremes() {
+        assert_eq!(
+            DbKey1i32::new(0, i32::MAX).face_neighbors(),
+            vec![DbKey1i32::new(0, i32::MAX - 1)]
+        );
+        assert_eq!(
+            DbKey3i32::from_coords(0, IVec3::MIN).face_neighbors().len(),
+            3
+        );
+        // Only 42 bits of each coordinate are representable.
+        let edge = (1 << 41) - 1;
+        assert_eq!(
+            DbKey3i64::from_coords(0, I64Vec3::new(edge, 0, 0))
+                .face_neighbors()
+                .len(),
+            5
+        );
+    }
+
+    #[test]
+    fn distant_ancestors_saturate() {
+        let key2 = DbKey2i32::from_coords(0, IVec2::new(-5, i32::MAX));
+        assert_eq!(key2.ancestor(40).coords(), IVec2::new(-1, 0));
+        assert_eq!(key2.ancestor(Level::MAX).coords(), IVec2::new(-1, 0));
+        assert_eq!(DbKey1i32::new(3, i32::MIN).ancestor(35).coord, -1);
+
+        let key3 = DbKey3i64::from_coords(1, I64Vec3::new(-(1 << 41), 7, 0));
+        assert_eq!(key3.ancestor(100).coords(), I64Vec3::new(-1, 0, 0));
+        let key2_64 = DbKey2i64::from_coords(0, I64Vec2::new(i64::MIN, i64::MAX));
+        assert_eq!(key2_64.ancestor(64).coords(), I64Vec2::new(-1, 0));
+    }
+
+    #[test]
+    fn checked_3i64_coords() {
+        let max = I64Vec3::splat(DbKey3i64::MAX_COORD);
+        assert_eq!(
+            DbKey3i64::checked_from_coords(0, max).map(|key| key.coords()),
+            Some(max)
+        );
+        assert_eq!(DbKey3i64::checked_from_coords(0, max + I64Vec3::X), None);
+        assert_eq!(
+            DbKey3i64::checked_from_coords(0, I64Vec3::splat(DbKey3i64::MIN_COORD - 1)),
+            None
+        );
+        // Children past the edge of the range aren't representable.
+        assert!(DbKey3i64::from_coords(1, max).children().is_empty());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "outside of the representable range")]
+    fn unrepresentable_3i64_coords_panic_in_debug() {
+        DbKey3i64::from_coords(0, I64Vec3::new(1 << 41, 0, 0));
+    }
+
+    #[test]
+    fn children_are_clamped_at_extremes() {
+        assert!(DbKey3i32::from_coords(0, IVec3::ZERO).children().is_empty());
+        assert!(DbKey2i32::from_coords(1, IVec2::MAX).children().is_empty());
+        assert!(DbKey1i32::new(1, i32::MIN).children().is_empty());
+
+        let top = DbKey3i32::from_coords(Level::MAX, IVec3::ONE);
+        assert_eq!(top.parent(), top);
+    }
+}
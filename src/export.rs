@@ -0,0 +1,165 @@
+use crate::db::AbortReason;
+use crate::{NoSharedAllocSerializer, DEFAULT_SCRATCH_SIZE};
+
+use rkyv::{check_archived_root, ser::Serializer, AlignedVec, Archive, Deserialize, Serialize};
+use sled::transaction::TransactionError;
+use sled::Tree;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Identifies the export format, so we fail early on anything else.
+pub const MAGIC: &[u8; 8] = b"GRIDDB01";
+
+/// The suffixes of the names of the trees in a map, in the order they are exported.
+pub const TREE_SUFFIXES: [&str; 7] = [
+    "meta",
+    "tags",
+    "working",
+    "backup",
+    "blobs",
+    "version-changes",
+    "version-graph",
+];
+
+/// Set in the header flags when the exported map is content-addressed.
+pub const CONTENT_ADDRESSED_FLAG: u8 = 1;
+
+/// The raw entries of one of the map's trees. `name` is the suffix of the tree name, like `working` for `{map_name}-working`.
+#[derive(Archive, Deserialize, Serialize)]
+#[archive(check_bytes)]
+pub struct ExportSection {
+    pub name: String,
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ExportSection {
+    pub fn from_tree(name: &str, tree: &Tree) -> sled::Result<Self> {
+        let entries = tree
+            .iter()
+            .map(|iter_result| iter_result.map(|(key, value)| (key.to_vec(), value.to_vec())))
+            .collect::<sled::Result<_>>()?;
+        Ok(Self {
+            name: name.to_owned(),
+            entries,
+        })
+    }
+}
+
+pub fn write_header(mut writer: impl Write, flags: u8, num_sections: u32) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[flags])?;
+    writer.write_all(&num_sections.to_le_bytes())
+}
+
+/// Returns the flags and number of sections.
+pub fn read_header(mut reader: impl Read) -> Result<(u8, u32), ImportError> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(ImportError::InvalidFormat("bad magic bytes"));
+    }
+    let mut flags = [0; 1];
+    reader.read_exact(&mut flags)?;
+    let mut num_sections = [0; 4];
+    reader.read_exact(&mut num_sections)?;
+    Ok((flags[0], u32::from_le_bytes(num_sections)))
+}
+
+/// Writes the archived `section` prefixed by its length.
+pub fn write_section(mut writer: impl Write, section: &ExportSection) -> io::Result<()> {
+    let mut serializer = NoSharedAllocSerializer::<DEFAULT_SCRATCH_SIZE>::default();
+    serializer.serialize_value(section).unwrap();
+    let bytes = serializer.into_serializer().into_inner();
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(&bytes)
+}
+
+/// Reads a section written by [`write_section`]. The bytes are validated, since they may come from anywhere.
+///
+/// The length prefix can't be trusted either, so the buffer only grows as bytes actually arrive.
+pub fn read_section(mut reader: impl Read) -> Result<ExportSection, ImportError> {
+    let mut len = [0; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    let mut bytes = AlignedVec::new();
+    if io::copy(&mut reader.by_ref().take(len), &mut bytes)? != len {
+        return Err(ImportError::InvalidFormat("truncated section"));
+    }
+    let archived = check_archived_root::<ExportSection>(&bytes)
+        .map_err(|_| ImportError::InvalidFormat("corrupt section"))?;
+    Ok(archived.deserialize(&mut rkyv::Infallible).unwrap())
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Sled(sled::Error),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<sled::Error> for ExportError {
+    fn from(e: sled::Error) -> Self {
+        Self::Sled(e)
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to write export: {}", e),
+            Self::Sled(e) => write!(f, "failed to read map: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    Sled(sled::Error),
+    /// The stream is not a valid export.
+    InvalidFormat(&'static str),
+    /// There is already a map with the requested name.
+    MapExists,
+}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<sled::Error> for ImportError {
+    fn from(e: sled::Error) -> Self {
+        Self::Sled(e)
+    }
+}
+
+/// Neither writing the imported trees nor opening the map ever aborts.
+impl From<TransactionError<AbortReason>> for ImportError {
+    fn from(e: TransactionError<AbortReason>) -> Self {
+        match e {
+            TransactionError::Storage(e) => Self::Sled(e),
+            TransactionError::Abort(_) => unreachable!("Importing never aborts"),
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read export: {}", e),
+            Self::Sled(e) => write!(f, "failed to write map: {}", e),
+            Self::InvalidFormat(reason) => write!(f, "invalid export: {}", reason),
+            Self::MapExists => write!(f, "a map with that name already exists"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
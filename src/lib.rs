@@ -1,20 +1,33 @@
 //! A [`sled`](https://crates.io/crates/sled) database mapping from Morton-encoded (Z-order) quadtree/octree nodes to arbitrary `[u8]` data.
 
 mod archived_buf;
+#[cfg(feature = "tokio")]
+mod async_db;
 mod backup_tree;
+mod blob_tree;
 mod change_encoder;
 mod db;
 mod db_key;
+mod export;
 mod meta_tree;
+mod read_cache;
 mod version_change_tree;
 mod version_graph_tree;
 mod working_tree;
 
+#[cfg(feature = "tokio")]
+pub use async_db::AsyncGridDb;
 pub use change_encoder::*;
-pub use db::GridDb;
+pub use db::{
+    write_working_versions, CompactionReport, GridDb, GridDbBuilder, GridDbStats, IntegrityIssue,
+    MergeConflict, MergeReport, RetryPolicy, WriteReport,
+};
 pub use db_key::*;
+pub use export::{ExportError, ImportError};
 pub use meta_tree::GridDbMetadata;
 pub use version_change_tree::VersionChanges;
+pub use version_graph_tree::{CommitMeta, GraphStats};
+pub use working_tree::{compute_reverse_changes, ExtentCursor, ExtentWatcher, WorkingTiles};
 
 use archived_buf::ArchivedBuf;
 
@@ -49,10 +62,29 @@ impl Version {
     pub const fn into_sled_key(self) -> [u8; 8] {
         self.number.to_be_bytes()
     }
+
+    pub fn from_sled_key(bytes: &[u8]) -> Self {
+        let mut number_bytes = [0; 8];
+        number_bytes.copy_from_slice(bytes);
+        Self::new(u64::from_be_bytes(number_bytes))
+    }
+}
+
+/// The name of the sled tree holding the `suffix` part of the map called `map_name`, like `mymap-working`.
+///
+/// Dashes in `map_name` are doubled, so a tree name splits into a map name and suffix in only one way and no two maps can share
+/// a tree. Names without dashes are unchanged. Maps with dashes in their names used to be stored under the undoubled names, and
+/// are moved when they're opened; see [`GridDb::migrate_legacy_tree_names`].
+fn tree_name(map_name: &str, suffix: &str) -> String {
+    format!("{}-{}", map_name.replace('-', "--"), suffix)
 }
 
 type SmallKeyHashMap<K, V> = AHashMap<K, V>;
 
+/// The default number of bytes of heap scratch space given to serializers. Anything that needs more scratch space falls back to
+/// allocating.
+pub const DEFAULT_SCRATCH_SIZE: usize = 8192;
+
 type NoSharedAllocSerializer<const N: usize> = CompositeSerializer<
     AlignedSerializer<AlignedVec>,
     FallbackScratch<HeapScratch<N>, AllocScratch>,
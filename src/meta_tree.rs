@@ -1,16 +1,27 @@
 use crate::db::AbortReason;
-use crate::{ArchivedIVec, Version};
+use crate::{tree_name, ArchivedIVec, Version};
 use rkyv::{
     ser::{serializers::CoreSerializer, Serializer},
-    Archive, Deserialize, Serialize,
+    Archive, Archived, Deserialize, Serialize,
 };
 
 use sled::{
-    transaction::{TransactionError, TransactionalTree, UnabortableTransactionError},
-    Tree,
+    transaction::{
+        abort, ConflictableTransactionResult, TransactionError, TransactionalTree,
+        UnabortableTransactionError,
+    },
+    IVec, Tree,
 };
 
 const META_KEY: &str = "META";
+/// Present once the keys of the backup tree are persisted, which lets us load the
+/// [`BackupKeyCache`](crate::backup_tree::BackupKeyCache) on open without scanning the backup tree. Maps that persisted them
+/// before they were split into [`BACKUP_KEY_PREFIX`] entries hold the concatenated sled keys here.
+const BACKUP_KEYS_KEY: &str = "BACKUP_KEYS";
+/// Followed by the sled key of each entry in the backup tree, so a write only inserts the keys it newly backed up.
+const BACKUP_KEY_PREFIX: &[u8] = b"BACKUP_KEY:";
+/// The largest version number taken by versions that weren't numbered by [`generate_version`], like imported ones.
+const VERSION_FLOOR_KEY: &str = "VERSION_FLOOR";
 
 #[derive(Archive, Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[archive_attr(derive(Eq, PartialEq))]
@@ -18,24 +29,35 @@ pub struct GridDbMetadata {
     pub grandparent_version: Option<Version>,
     pub parent_version: Option<Version>,
     pub working_version: Version,
+    /// Incremented by every write of the metadata, so a handle can tell that another handle changed it since it was read.
+    pub meta_generation: u64,
+}
+
+/// The metadata of maps created before [`GridDbMetadata::meta_generation`] was recorded.
+#[derive(Archive, Deserialize, Serialize)]
+struct LegacyGridDbMetadata {
+    grandparent_version: Option<Version>,
+    parent_version: Option<Version>,
+    working_version: Version,
 }
 
 pub fn open_meta_tree(
     map_name: &str,
     db: &sled::Db,
 ) -> Result<(Tree, GridDbMetadata), TransactionError<AbortReason>> {
-    let tree = db.open_tree(format!("{}-meta", map_name))?;
+    let tree = db.open_tree(tree_name(map_name, "meta"))?;
 
     let cached_meta = tree.transaction(|txn| {
         if let Some(cached_meta) = read_meta(txn)? {
-            Ok(cached_meta.deserialize())
+            Ok(cached_meta)
         } else {
             // First time opening this tree. Write the initial values.
-            let working_version = Version::new(txn.generate_id()?);
+            let working_version = generate_version(txn)?;
             let meta = GridDbMetadata {
                 grandparent_version: None,
                 parent_version: None,
                 working_version,
+                meta_generation: 0,
             };
             write_meta(txn, &meta)?;
             Ok(meta)
@@ -51,7 +73,7 @@ pub fn write_meta(
 ) -> Result<(), UnabortableTransactionError> {
     // TODO: one liner?
     // https://github.com/rkyv/rkyv/issues/232
-    let mut serializer = CoreSerializer::<40, 0>::default();
+    let mut serializer = CoreSerializer::<48, 0>::default();
     serializer.serialize_value(meta).unwrap();
     let bytes = serializer.into_serializer().into_inner();
 
@@ -62,9 +84,166 @@ pub fn write_meta(
 
 pub fn read_meta(
     txn: &TransactionalTree,
-) -> Result<Option<ArchivedIVec<GridDbMetadata>>, UnabortableTransactionError> {
-    let data = txn.get(META_KEY)?;
-    Ok(data.map(|b| unsafe { ArchivedIVec::<GridDbMetadata>::new(b) }))
+) -> Result<Option<GridDbMetadata>, UnabortableTransactionError> {
+    Ok(txn.get(META_KEY)?.map(decode_meta))
+}
+
+/// Like [`read_meta`], but outside of a transaction.
+pub fn load_meta(tree: &Tree) -> sled::Result<Option<GridDbMetadata>> {
+    Ok(tree.get(META_KEY)?.map(decode_meta))
+}
+
+fn decode_meta(bytes: IVec) -> GridDbMetadata {
+    if bytes.len() == std::mem::size_of::<Archived<LegacyGridDbMetadata>>() {
+        let legacy = unsafe { ArchivedIVec::<LegacyGridDbMetadata>::new(bytes) }.deserialize();
+        GridDbMetadata {
+            grandparent_version: legacy.grandparent_version,
+            parent_version: legacy.parent_version,
+            working_version: legacy.working_version,
+            meta_generation: 0,
+        }
+    } else {
+        unsafe { ArchivedIVec::<GridDbMetadata>::new(bytes) }.deserialize()
+    }
+}
+
+/// Generates the number of a new version of this map. That's the next sled ID, unless the ID isn't above the version floor
+/// (see [`raise_version_floor`]), in which case it's the number after the floor.
+pub fn generate_version(txn: &TransactionalTree) -> Result<Version, UnabortableTransactionError> {
+    let id = txn.generate_id()?;
+    let floor = txn
+        .get(VERSION_FLOOR_KEY)?
+        .map(|bytes| Version::from_sled_key(&bytes));
+    match floor {
+        Some(floor) if floor.number >= id => {
+            // sled's IDs will catch up with the floor eventually, but until then we take the numbers above it ourselves.
+            let version = Version::new(floor.number + 1);
+            txn.insert(VERSION_FLOOR_KEY, version.into_sled_key().as_ref())?;
+            Ok(version)
+        }
+        _ => Ok(Version::new(id)),
+    }
+}
+
+/// Makes sure that [`generate_version`] never returns `version` or anything below it. Use this when a version number wasn't
+/// generated by this map.
+///
+/// sled can only advance its ID generator one ID at a time, so instead of catching it up, we remember the largest taken
+/// number.
+pub fn raise_version_floor(
+    txn: &TransactionalTree,
+    version: Version,
+) -> Result<(), UnabortableTransactionError> {
+    let floor = txn
+        .get(VERSION_FLOOR_KEY)?
+        .map(|bytes| Version::from_sled_key(&bytes));
+    if !matches!(floor, Some(floor) if floor >= version) {
+        txn.insert(VERSION_FLOOR_KEY, version.into_sled_key().as_ref())?;
+    }
+    Ok(())
+}
+
+/// Aborts with [`AbortReason::MetaConflict`] unless the stored metadata is still at the generation of `cached_meta`, i.e. no
+/// other handle wrote it since `cached_meta` was read.
+pub fn check_meta_generation(
+    txn: &TransactionalTree,
+    cached_meta: &GridDbMetadata,
+) -> ConflictableTransactionResult<(), AbortReason> {
+    let stored_generation = read_meta(txn)?.map_or(0, |meta| meta.meta_generation);
+    if stored_generation != cached_meta.meta_generation {
+        return abort(AbortReason::MetaConflict);
+    }
+    Ok(())
+}
+
+fn backup_key_entry(key: &[u8]) -> Vec<u8> {
+    [BACKUP_KEY_PREFIX, key].concat()
+}
+
+/// Adds `new_keys` to the persisted backup key set. This must happen in the same transaction that writes the new keys to the
+/// backup tree.
+pub fn append_backup_keys<'a>(
+    txn: &TransactionalTree,
+    new_keys: impl IntoIterator<Item = &'a IVec>,
+) -> Result<(), UnabortableTransactionError> {
+    for key in new_keys {
+        txn.insert(backup_key_entry(key), IVec::default())?;
+    }
+    Ok(())
+}
+
+/// Empties the persisted backup key set, which holds exactly `keys`. This must happen in the same transaction that clears the
+/// backup tree.
+pub fn clear_backup_keys(
+    txn: &TransactionalTree,
+    keys: impl IntoIterator<Item = impl AsRef<[u8]>>,
+) -> Result<(), UnabortableTransactionError> {
+    txn.insert(BACKUP_KEYS_KEY, IVec::default())?;
+    for key in keys {
+        txn.remove(backup_key_entry(key.as_ref()))?;
+    }
+    Ok(())
+}
+
+/// Replaces the persisted backup key set with `keys`.
+pub fn write_backup_keys(
+    tree: &Tree,
+    keys: impl IntoIterator<Item = impl AsRef<[u8]>>,
+) -> sled::Result<()> {
+    let mut batch = sled::Batch::default();
+    for iter_result in tree.scan_prefix(BACKUP_KEY_PREFIX) {
+        let (entry, _) = iter_result?;
+        batch.remove(entry);
+    }
+    for key in keys {
+        batch.insert(backup_key_entry(key.as_ref()), IVec::default());
+    }
+    batch.insert(BACKUP_KEYS_KEY, IVec::default());
+    tree.apply_batch(batch)
+}
+
+/// Returns the sled keys in the persisted backup key set, each `key_len` bytes long, or `None` for maps created before the
+/// set was persisted.
+pub fn read_backup_keys(tree: &Tree, key_len: usize) -> sled::Result<Option<Vec<IVec>>> {
+    let legacy_keys = match tree.get(BACKUP_KEYS_KEY)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let mut keys: Vec<IVec> = legacy_keys.chunks_exact(key_len).map(IVec::from).collect();
+    for iter_result in tree.scan_prefix(BACKUP_KEY_PREFIX) {
+        let (entry, _) = iter_result?;
+        keys.push(IVec::from(&entry[BACKUP_KEY_PREFIX.len()..]));
+    }
+    Ok(Some(keys))
+}
+
+pub fn open_tag_tree(map_name: &str, db: &sled::Db) -> sled::Result<Tree> {
+    db.open_tree(tree_name(map_name, "tags"))
+}
+
+/// Points the tag `name` at `version`, replacing any previous target.
+pub fn write_tag(tree: &Tree, name: &str, version: Version) -> sled::Result<()> {
+    tree.insert(name.as_bytes(), version.into_sled_key().as_ref())?;
+    Ok(())
+}
+
+pub fn read_tag(tree: &Tree, name: &str) -> sled::Result<Option<Version>> {
+    Ok(tree
+        .get(name.as_bytes())?
+        .map(|bytes| Version::from_sled_key(&bytes)))
+}
+
+/// All tags, sorted by name.
+pub fn read_all_tags(tree: &Tree) -> sled::Result<Vec<(String, Version)>> {
+    tree.iter()
+        .map(|iter_result| -> sled::Result<_> {
+            let (name_bytes, version_bytes) = iter_result?;
+            Ok((
+                String::from_utf8_lossy(&name_bytes).into_owned(),
+                Version::from_sled_key(&version_bytes),
+            ))
+        })
+        .collect()
 }
 
 // ████████╗███████╗███████╗████████╗
@@ -89,6 +268,7 @@ mod tests {
             grandparent_version: None,
             parent_version: Some(Version::new(20)),
             working_version: Version::new(18),
+            meta_generation: 1,
         };
         let _: Result<(), TransactionError<()>> = tree.transaction(|txn| {
             write_meta(txn, &new_meta)?;
@@ -99,4 +279,39 @@ mod tests {
         let (_tree, cached_meta) = open_meta_tree("mymap", &db).unwrap();
         assert_eq!(cached_meta, new_meta);
     }
+
+    #[test]
+    fn backup_keys_are_stored_per_key() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let (tree, _) = open_meta_tree("mymap", &db).unwrap();
+        assert_eq!(read_backup_keys(&tree, 2).unwrap(), None);
+
+        // The concatenated keys of older maps are still read.
+        tree.insert(BACKUP_KEYS_KEY, vec![1, 1, 2, 2]).unwrap();
+        let new_keys = [IVec::from(vec![3, 3]), IVec::from(vec![4, 4])];
+        let _: Result<(), TransactionError<()>> = tree.transaction(|txn| {
+            append_backup_keys(txn, &new_keys[..1])?;
+            append_backup_keys(txn, &new_keys[1..])?;
+            Ok(())
+        });
+        let keys = read_backup_keys(&tree, 2).unwrap().unwrap();
+        assert_eq!(
+            keys,
+            [[1, 1], [2, 2], [3, 3], [4, 4]].map(|k| IVec::from(&k[..]))
+        );
+        // Every write only adds entries for its own keys.
+        assert_eq!(tree.scan_prefix(BACKUP_KEY_PREFIX).count(), 2);
+
+        let _: Result<(), TransactionError<()>> = tree.transaction(|txn| {
+            clear_backup_keys(txn, keys.iter())?;
+            Ok(())
+        });
+        assert_eq!(read_backup_keys(&tree, 2).unwrap(), Some(vec![]));
+
+        write_backup_keys(&tree, [[5u8, 5]]).unwrap();
+        assert_eq!(
+            read_backup_keys(&tree, 2).unwrap(),
+            Some(vec![IVec::from(vec![5, 5])])
+        );
+    }
 }
@@ -0,0 +1,127 @@
+use crate::{Change, SmallKeyHashMap};
+
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+/// A bounded cache of deserialized working version values that evicts the least recently used entry when it's full.
+///
+/// Absent values are cached as `None`, so repeated reads of empty keys are also served from memory.
+pub struct ReadCache<K> {
+    capacity: usize,
+    /// Each value along with the tick of its last use.
+    entries: SmallKeyHashMap<K, (Option<Change>, u64)>,
+    /// The cached keys ordered by the tick of their last use, oldest first.
+    recency: BTreeMap<u64, K>,
+    next_tick: u64,
+}
+
+impl<K> ReadCache<K>
+where
+    K: Clone + Eq + Hash,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Default::default(),
+            recency: Default::default(),
+            next_tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the cached value of `key` (which may be `None` for an absent value) and marks it as most recently used, or
+    /// returns `None` on a miss.
+    pub fn get(&mut self, key: &K) -> Option<Option<Change>> {
+        let tick = self.tick();
+        let (value, last_used) = self.entries.get_mut(key)?;
+        let key = self
+            .recency
+            .remove(last_used)
+            .expect("BUG: cached key missing from recency order");
+        *last_used = tick;
+        self.recency.insert(tick, key);
+        Some(value.clone())
+    }
+
+    /// Caches `value` for `key` as the most recently used entry, evicting the least recently used entry if the cache is full.
+    pub fn insert(&mut self, key: K, value: Option<Change>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.invalidate(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some(&oldest) = self.recency.keys().next() {
+                let evicted = self.recency.remove(&oldest).unwrap();
+                self.entries.remove(&evicted);
+            }
+        }
+        let tick = self.tick();
+        self.recency.insert(tick, key.clone());
+        self.entries.insert(key, (value, tick));
+    }
+
+    pub fn invalidate(&mut self, key: &K) {
+        if let Some((_, last_used)) = self.entries.remove(key) {
+            self.recency.remove(&last_used);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    fn tick(&mut self) -> u64 {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        tick
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(byte: u8) -> Option<Change> {
+        Some(Change::Insert(Box::new([byte])))
+    }
+
+    #[test]
+    fn evicts_least_recently_used_at_capacity() {
+        let mut cache = ReadCache::new(2);
+        cache.insert(1, value(1));
+        cache.insert(2, value(2));
+        // Touch 1 so that 2 becomes the least recently used.
+        assert_eq!(cache.get(&1), Some(value(1)));
+
+        cache.insert(3, None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(value(1)));
+        assert_eq!(cache.get(&3), Some(None));
+
+        // Replacing a cached key doesn't evict anything.
+        cache.insert(3, value(3));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some(value(1)));
+        assert_eq!(cache.get(&3), Some(value(3)));
+    }
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        let mut cache = ReadCache::new(0);
+        cache.insert(1, value(1));
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.get(&1), None);
+    }
+}
@@ -1,13 +1,15 @@
-use super::{ArchivedIVec, Change, DbKey, EncodedChanges, Version};
-use crate::NoSharedAllocSerializer;
+use super::{ArchivedIVec, Change, ChangeEncoder, DbKey, EncodedChanges, Version};
+use crate::{tree_name, NoSharedAllocSerializer, DEFAULT_SCRATCH_SIZE};
 
+use rkyv::ser::serializers::AlignedSerializer;
 use rkyv::ser::Serializer;
-use rkyv::{Archive, Archived, Deserialize, Serialize};
+use rkyv::{AlignedVec, Archive, Archived, Deserialize, Infallible, Serialize};
 use sled::transaction::TransactionalTree;
 use sled::{transaction::UnabortableTransactionError, Tree};
 use std::collections::BTreeMap;
 
 #[derive(Archive, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[archive(check_bytes)]
 pub struct VersionChanges<K> {
     /// The full set of changes made between `parent_version` and this version.
     ///
@@ -37,22 +39,72 @@ where
     }
 }
 
+/// Re-encodes archived changes so they can be written to the working tree.
+pub fn encode_archived_changes<K>(changes: &Archived<VersionChanges<K>>) -> EncodedChanges
+where
+    K: DbKey,
+    Archived<K>: Deserialize<K, Infallible> + Ord,
+{
+    let mut encoder = ChangeEncoder::default();
+    for (key, change) in changes.changes.iter() {
+        let key: K = key.deserialize(&mut Infallible).unwrap();
+        // PERF: in principle we should be able to copy the compressed bytes directly from the archived change, but the types
+        // aren't set up for that yet
+        let change = change.deserialize(&mut Infallible).unwrap();
+        encoder.add_change(key, change);
+    }
+    encoder.encode()
+}
+
+/// Composes a sequence of archived changes into a single [`VersionChanges`] holding the net change for each key. When more than
+/// one archive changes the same key, the last one wins.
+pub fn compose_archived_changes<'a, K>(
+    archives: impl IntoIterator<Item = &'a Archived<VersionChanges<K>>>,
+) -> VersionChanges<K>
+where
+    K: DbKey + 'a,
+    Archived<K>: Deserialize<K, Infallible> + Ord,
+{
+    let mut composed = BTreeMap::new();
+    for changes in archives {
+        for (key, change) in changes.changes.iter() {
+            let key: K = key.deserialize(&mut Infallible).unwrap();
+            composed.insert(key, change.deserialize(&mut Infallible).unwrap());
+        }
+    }
+    VersionChanges::new(composed)
+}
+
 pub fn open_version_change_tree(map_name: &str, db: &sled::Db) -> sled::Result<Tree> {
-    db.open_tree(format!("{}-version-changes", map_name))
+    db.open_tree(tree_name(map_name, "version-changes"))
+}
+
+/// Serializes `changes` into a buffer that starts out with room for `capacity` bytes.
+pub fn serialize_version_changes<K>(changes: &VersionChanges<K>, capacity: usize) -> AlignedVec
+where
+    K: DbKey,
+    Archived<K>: Ord,
+{
+    let mut serializer = NoSharedAllocSerializer::<DEFAULT_SCRATCH_SIZE>::new(
+        AlignedSerializer::new(AlignedVec::with_capacity(capacity)),
+        Default::default(),
+        Infallible,
+    );
+    serializer.serialize_value(changes).unwrap();
+    serializer.into_serializer().into_inner()
 }
 
 pub fn archive_version<K>(
     txn: &TransactionalTree,
     version: Version,
     changes: &VersionChanges<K>,
+    capacity: usize,
 ) -> Result<(), UnabortableTransactionError>
 where
     K: DbKey,
     Archived<K>: Ord,
 {
-    let mut serializer = NoSharedAllocSerializer::<8192>::default();
-    serializer.serialize_value(changes).unwrap();
-    let changes_bytes = serializer.into_serializer().into_inner();
+    let changes_bytes = serialize_version_changes(changes, capacity);
     txn.insert(&version.into_sled_key(), changes_bytes.as_ref())?;
     Ok(())
 }
@@ -109,7 +161,7 @@ mod tests {
                         == ArchivedOption::<ArchivedIVec<VersionChanges<DbKey3i32>>>::None
                 );
 
-                archive_version(txn, v0, &changes).unwrap();
+                archive_version(txn, v0, &changes, 0).unwrap();
 
                 let owned_archive = remove_archived_version(txn, Version::new(0))?.unwrap();
 
@@ -1,32 +1,111 @@
-use crate::{db::AbortReason, ArchivedIVec, Version};
+use crate::{db::AbortReason, tree_name, ArchivedIVec, NoSharedAllocSerializer, Version};
 
-use rkyv::{
-    ser::{serializers::CoreSerializer, Serializer},
-    AlignedBytes, Archive, Deserialize, Serialize,
-};
+use rkyv::{ser::Serializer, AlignedVec, Archive, Archived, Deserialize, Serialize};
 use sled::{
     transaction::{
         abort, ConflictableTransactionError, TransactionalTree, UnabortableTransactionError,
     },
-    Tree,
+    IVec, Tree,
 };
+use std::collections::{btree_map, BTreeMap, BTreeSet, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Archive, Debug, Deserialize, Serialize)]
 pub struct VersionNode {
     /// The version immediately before this one.
     pub parent_version: Option<Version>,
+    /// Any additional parents of a merge version. Empty in the common single-parent case.
+    pub merge_parents: Vec<Version>,
+    /// Milliseconds since the Unix epoch when this version was committed. `None` for versions committed before this was
+    /// recorded.
+    pub created_at: Option<u64>,
+    /// A user-provided description of the commit, like the author's name.
+    pub label: Option<String>,
 }
 
 impl VersionNode {
-    pub fn serialize(&self) -> AlignedBytes<16> {
-        let mut serializer = CoreSerializer::<16, 0>::default();
+    pub fn new(parent_version: Option<Version>) -> Self {
+        Self {
+            parent_version,
+            merge_parents: Vec::new(),
+            created_at: None,
+            label: None,
+        }
+    }
+
+    pub fn with_commit_meta(parent_version: Option<Version>, meta: CommitMeta) -> Self {
+        Self {
+            created_at: meta.created_at,
+            label: meta.label,
+            ..Self::new(parent_version)
+        }
+    }
+
+    pub fn commit_meta(&self) -> CommitMeta {
+        CommitMeta {
+            created_at: self.created_at,
+            label: self.label.clone(),
+        }
+    }
+
+    /// All parents of this version, starting with `parent_version`.
+    pub fn parents(&self) -> impl Iterator<Item = Version> + '_ {
+        self.parent_version
+            .iter()
+            .chain(self.merge_parents.iter())
+            .copied()
+    }
+
+    pub fn serialize(&self) -> AlignedVec {
+        let mut serializer = NoSharedAllocSerializer::<64>::default();
         serializer.serialize_value(self).unwrap();
         serializer.into_serializer().into_inner()
     }
 }
 
+/// Metadata recorded with a committed version.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CommitMeta {
+    /// Milliseconds since the Unix epoch.
+    pub created_at: Option<u64>,
+    /// A user-provided description of the commit, like the author's name.
+    pub label: Option<String>,
+}
+
+impl CommitMeta {
+    /// Timestamped with the current system time and no label.
+    pub fn now() -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .ok();
+        Self {
+            created_at,
+            label: None,
+        }
+    }
+}
+
+/// The original single-parent node format.
+///
+/// These always archived to exactly `size_of::<Archived<LegacyVersionNode>>()` bytes, while a [`VersionNode`] is always
+/// larger, so the length of the stored bytes tells us which format we're reading. Legacy nodes have no [`CommitMeta`].
+#[derive(Archive, Deserialize, Serialize)]
+struct LegacyVersionNode {
+    parent_version: Option<Version>,
+}
+
+fn decode_version_node(bytes: IVec) -> VersionNode {
+    if bytes.len() == std::mem::size_of::<Archived<LegacyVersionNode>>() {
+        let legacy = unsafe { ArchivedIVec::<LegacyVersionNode>::new(bytes) }.deserialize();
+        VersionNode::new(legacy.parent_version)
+    } else {
+        unsafe { ArchivedIVec::<VersionNode>::new(bytes) }.deserialize()
+    }
+}
+
 pub fn open_version_graph_tree(map_name: &str, db: &sled::Db) -> sled::Result<Tree> {
-    db.open_tree(format!("{}-version-graph", map_name))
+    db.open_tree(tree_name(map_name, "version-graph"))
 }
 
 pub fn link_version(
@@ -39,6 +118,114 @@ pub fn link_version(
     Ok(())
 }
 
+pub fn read_version_node(
+    txn: &TransactionalTree,
+    version: Version,
+) -> Result<Option<VersionNode>, UnabortableTransactionError> {
+    Ok(txn.get(version.into_sled_key())?.map(decode_version_node))
+}
+
+/// Summary of the shape of the version graph.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct GraphStats {
+    pub node_count: u64,
+    /// Versions without any parent.
+    pub root_count: u64,
+    /// Versions that are no one's parent.
+    pub leaf_count: u64,
+    /// The largest number of parent links between any version and a root.
+    pub max_depth: usize,
+}
+
+pub fn read_all_version_nodes(tree: &Tree) -> sled::Result<BTreeMap<Version, VersionNode>> {
+    let mut nodes = BTreeMap::new();
+    for iter_result in tree.iter() {
+        let (key_bytes, node_bytes) = iter_result?;
+        nodes.insert(
+            Version::from_sled_key(&key_bytes),
+            decode_version_node(node_bytes),
+        );
+    }
+    Ok(nodes)
+}
+
+/// Returns `true` if `ancestor` is `version` or can be reached from `version` by following any parent links.
+pub fn is_ancestor_or_self(
+    nodes: &BTreeMap<Version, VersionNode>,
+    ancestor: Version,
+    version: Version,
+) -> bool {
+    let mut stack = vec![version];
+    let mut visited = BTreeSet::new();
+    while let Some(v) = stack.pop() {
+        if v == ancestor {
+            return true;
+        }
+        if visited.insert(v) {
+            stack.extend(nodes.get(&v).into_iter().flat_map(|node| node.parents()));
+        }
+    }
+    false
+}
+
+pub fn compute_graph_stats(tree: &Tree) -> sled::Result<GraphStats> {
+    let nodes = read_all_version_nodes(tree)?;
+
+    let mut parents = BTreeSet::new();
+    let mut root_count = 0;
+    for node in nodes.values() {
+        if node.parent_version.is_none() && node.merge_parents.is_empty() {
+            root_count += 1;
+        }
+        parents.extend(node.parents());
+    }
+    let leaf_count = nodes.keys().filter(|v| !parents.contains(*v)).count() as u64;
+
+    // Depth is the longest path to a root, which we memoize so each node is only resolved once.
+    let mut depths: BTreeMap<Version, usize> = BTreeMap::new();
+    for &start in nodes.keys() {
+        let mut stack = vec![start];
+        while let Some(&version) = stack.last() {
+            if depths.contains_key(&version) {
+                stack.pop();
+                continue;
+            }
+            // Parents that are missing from the graph are treated like roots.
+            let node_parents: Vec<_> = nodes
+                .get(&version)
+                .map(|n| n.parents().filter(|p| nodes.contains_key(p)).collect())
+                .unwrap_or_default();
+            let unresolved: Vec<_> = node_parents
+                .iter()
+                .filter(|p| !depths.contains_key(p))
+                .copied()
+                .collect();
+            if unresolved.is_empty() {
+                let depth = node_parents
+                    .iter()
+                    .map(|p| depths[p] + 1)
+                    .max()
+                    .unwrap_or(0);
+                depths.insert(version, depth);
+                stack.pop();
+            } else {
+                stack.extend(unresolved);
+            }
+        }
+    }
+
+    Ok(GraphStats {
+        node_count: nodes.len() as u64,
+        root_count,
+        leaf_count,
+        max_depth: depths.values().copied().max().unwrap_or(0),
+    })
+}
+
+pub fn get_version_node(tree: &Tree, version: Version) -> sled::Result<Option<VersionNode>> {
+    Ok(tree.get(version.into_sled_key())?.map(decode_version_node))
+}
+
 pub struct VersionPath {
     /// The path from `start_version` to `end_version`, inclusive.
     pub path: Vec<Version>,
@@ -51,94 +238,193 @@ pub fn find_path_between_versions(
     start_version: Version,
     end_version: Version,
 ) -> Result<VersionPath, ConflictableTransactionError<AbortReason>> {
-    // First we search through the ancestors of start_version until hitting the root.
-    let (path_result, start_path) = find_ancestor_path(txn, start_version, end_version)?;
-    if let PathResult::FoundEnd = path_result {
-        return Ok(start_path);
+    let end_parent = match read_version_node(txn, end_version)? {
+        Some(node) => node.parent_version,
+        None => return abort(AbortReason::NoPathExistsToRoot),
+    };
+
+    // Archives only undo the changes since a version's first parent, so the path can't take any merge parent links.
+    let start_ancestors = find_ancestors(txn, start_version, false)?;
+    if start_ancestors.contains_key(&end_version) {
+        let mut path = walk_to_start(&start_ancestors, end_version);
+        path.reverse();
+        return Ok(VersionPath { path, end_parent });
     }
 
     // If we didn't see the end_version, then it's not an ancestor, so we need to find the nearest common ancestor.
-    let start_root_version = *start_path.path.last().unwrap();
-    let (_path_result, mut end_path) = find_ancestor_path(txn, end_version, start_root_version)?;
-    let end_root_version = *end_path.path.last().unwrap();
-
-    if start_root_version != end_root_version {
+    let end_ancestors = find_ancestors(txn, end_version, false)?;
+    let join = if let Some(join) = nearest_common_ancestor(&start_ancestors, &end_ancestors) {
+        join
+    } else {
         // No path exists. Programmer error?
         return abort(AbortReason::NoPathExists);
-    }
+    };
 
-    // Compare paths to the root to find the nearest common ancestor.
-    let mut start_join = 0;
-    let mut finish_join = 0;
-    for ((i1, v1), (i2, v2)) in start_path
-        .path
-        .iter()
-        .enumerate()
-        .rev()
-        .zip(end_path.path.iter().enumerate().rev())
-    {
-        if v1 != v2 {
-            // The previous index held the nearest common ancestor.
-            break;
-        }
-        start_join = i1;
-        finish_join = i2;
-    }
+    let mut path = walk_to_start(&start_ancestors, join);
+    path.reverse();
+    path.extend(walk_to_start(&end_ancestors, join).into_iter().skip(1));
 
-    let mut path = start_path.path[..=start_join].to_vec();
-    let further_slice = &mut end_path.path[..finish_join];
-    further_slice.reverse();
-    path.extend_from_slice(further_slice);
+    Ok(VersionPath { path, end_parent })
+}
 
-    Ok(VersionPath {
-        path,
-        end_parent: end_path.end_parent,
-    })
+/// Finds the common ancestor of `a` and `b` (either of which may be the ancestor) with the fewest parent links to both, or
+/// `None` if they don't share a root.
+pub fn find_nearest_common_ancestor(
+    txn: &TransactionalTree,
+    a: Version,
+    b: Version,
+) -> Result<Option<Version>, ConflictableTransactionError<AbortReason>> {
+    let a_ancestors = find_ancestors(txn, a, true)?;
+    let b_ancestors = find_ancestors(txn, b, true)?;
+    Ok(nearest_common_ancestor(&a_ancestors, &b_ancestors))
 }
 
-/// Finds a path along only ancestors, starting at `start_version` and ending at either `end_version` or the root ancestor,
-/// whichever comes first.
-pub fn find_ancestor_path(
+fn nearest_common_ancestor(
+    a_ancestors: &BTreeMap<Version, AncestorStep>,
+    b_ancestors: &BTreeMap<Version, AncestorStep>,
+) -> Option<Version> {
+    a_ancestors
+        .iter()
+        .filter_map(|(version, a_step)| {
+            b_ancestors
+                .get(version)
+                .map(|b_step| (a_step.distance + b_step.distance, *version))
+        })
+        .min()
+        .map(|(_, version)| version)
+}
+
+struct AncestorStep {
+    /// The number of parent links followed to reach this ancestor.
+    distance: usize,
+    /// The version we came from when we first reached this ancestor.
+    child: Option<Version>,
+}
+
+/// Visits every ancestor of `start_version` (including itself) breadth-first, following the `parent_version` of each version,
+/// and its merge parents too if `follow_merge_parents` is `true`.
+fn find_ancestors(
     txn: &TransactionalTree,
     start_version: Version,
-    end_version: Version,
-) -> Result<(PathResult, VersionPath), ConflictableTransactionError<AbortReason>> {
-    let mut path = vec![start_version];
-
-    // First we search through the ancestors of start_version until hitting the root.
-    let mut current_version = start_version;
-    while let Some(node_bytes) = txn.get(current_version.into_sled_key())? {
-        let node = unsafe { ArchivedIVec::<VersionNode>::new(node_bytes) }.deserialize();
-        if current_version == end_version {
-            return Ok((
-                PathResult::FoundEnd,
-                VersionPath {
-                    path,
-                    end_parent: node.parent_version,
-                },
-            ));
-        }
-
-        if let Some(parent) = node.parent_version {
-            path.push(parent);
-            current_version = parent;
+    follow_merge_parents: bool,
+) -> Result<BTreeMap<Version, AncestorStep>, ConflictableTransactionError<AbortReason>> {
+    let mut visited = BTreeMap::new();
+    visited.insert(
+        start_version,
+        AncestorStep {
+            distance: 0,
+            child: None,
+        },
+    );
+    let mut queue = VecDeque::from([start_version]);
+    while let Some(version) = queue.pop_front() {
+        let node = if let Some(node) = read_version_node(txn, version)? {
+            node
         } else {
-            // This must be the root version.
-            return Ok((
-                PathResult::FoundRoot,
-                VersionPath {
-                    path,
-                    end_parent: None,
-                },
-            ));
+            // We expect all nodes to have a path to the root.
+            return abort(AbortReason::NoPathExistsToRoot);
+        };
+        let distance = visited[&version].distance + 1;
+        let parents: Vec<_> = if follow_merge_parents {
+            node.parents().collect()
+        } else {
+            node.parent_version.into_iter().collect()
+        };
+        for parent in parents {
+            if let btree_map::Entry::Vacant(entry) = visited.entry(parent) {
+                entry.insert(AncestorStep {
+                    distance,
+                    child: Some(version),
+                });
+                queue.push_back(parent);
+            }
         }
     }
+    Ok(visited)
+}
 
-    // We expect all nodes to have a path to the root.
-    abort(AbortReason::NoPathExistsToRoot)
+/// Follows the `child` links from `ancestor` back to the version where the search started, inclusive.
+fn walk_to_start(ancestors: &BTreeMap<Version, AncestorStep>, ancestor: Version) -> Vec<Version> {
+    let mut path = vec![ancestor];
+    let mut current = ancestor;
+    while let Some(child) = ancestors[&current].child {
+        path.push(child);
+        current = child;
+    }
+    path
 }
 
-pub enum PathResult {
-    FoundRoot,
-    FoundEnd,
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rkyv::ser::serializers::CoreSerializer;
+    use sled::transaction::TransactionError;
+
+    #[test]
+    fn find_path_skips_merge_parents() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let tree = open_version_graph_tree("mymap", &db).unwrap();
+
+        let [root, a, b, merge] = [0, 1, 2, 3].map(Version::new);
+
+        let result: Result<_, TransactionError<AbortReason>> = tree.transaction(|txn| {
+            link_version(txn, root, VersionNode::new(None))?;
+            link_version(txn, a, VersionNode::new(Some(root)))?;
+            link_version(txn, b, VersionNode::new(Some(root)))?;
+            link_version(
+                txn,
+                merge,
+                VersionNode {
+                    merge_parents: vec![b],
+                    ..VersionNode::new(Some(a))
+                },
+            )?;
+
+            let from_a = find_path_between_versions(txn, a, merge)?;
+            let from_b = find_path_between_versions(txn, b, merge)?;
+            let to_b = find_path_between_versions(txn, merge, b)?;
+            let common = find_nearest_common_ancestor(txn, b, merge)?;
+            Ok((from_a.path, from_b.path, to_b.path, to_b.end_parent, common))
+        });
+        let (from_a, from_b, to_b, to_b_parent, common) = result.unwrap();
+
+        assert_eq!(from_a, vec![a, merge]);
+        // There's no archive for the merge parent link, so the path goes around through the root.
+        assert_eq!(from_b, vec![b, root, a, merge]);
+        assert_eq!(to_b, vec![merge, a, root, b]);
+        assert_eq!(to_b_parent, Some(root));
+        // Ancestry still follows merge parents.
+        assert_eq!(common, Some(b));
+    }
+
+    #[test]
+    fn read_legacy_single_parent_node() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let tree = open_version_graph_tree("mymap", &db).unwrap();
+
+        let mut serializer = CoreSerializer::<16, 0>::default();
+        serializer
+            .serialize_value(&LegacyVersionNode {
+                parent_version: Some(Version::new(7)),
+            })
+            .unwrap();
+        let legacy_bytes = serializer.into_serializer().into_inner();
+        tree.insert(Version::new(8).into_sled_key(), legacy_bytes.as_ref())
+            .unwrap();
+
+        let node: Result<_, TransactionError> =
+            tree.transaction(|txn| Ok(read_version_node(txn, Version::new(8))?));
+        let node = node.unwrap().unwrap();
+        assert_eq!(node.parent_version, Some(Version::new(7)));
+        assert!(node.merge_parents.is_empty());
+        assert_eq!(node.commit_meta(), CommitMeta::default());
+    }
 }
@@ -1,14 +1,33 @@
-use super::{
-    ArchivedChange, ArchivedChangeIVec, ArchivedIVec, Change, DbKey,
-    EncodedChanges,
-};
+use super::{ArchivedChange, ArchivedChangeIVec, ArchivedIVec, Change, DbKey, EncodedChanges};
 use crate::backup_tree::BackupKeyCache;
+use crate::blob_tree::read_blob;
+use crate::{tree_name, Level};
 
+use ilattice::prelude::Extent;
 use sled::transaction::{TransactionalTree, UnabortableTransactionError};
-use sled::{IVec, Tree};
+use sled::{Event, IVec, Subscriber, Tree};
+use std::collections::BTreeMap;
+use std::ops::Bound;
 
 pub fn open_working_tree(map_name: &str, db: &sled::Db) -> sled::Result<Tree> {
-    db.open_tree(format!("{}-working", map_name))
+    db.open_tree(tree_name(map_name, "working"))
+}
+
+/// Turns a value `stored` in the working tree into the value it stands for, following the reference into `blob_tree` if the
+/// map is content-addressed, and checks the value against its checksum. Every read that hands out stored values goes
+/// through here.
+///
+/// `stored` must not be a [`Change::Remove`], which has no value to resolve.
+pub fn resolve_stored_value(
+    blob_tree: Option<&Tree>,
+    stored: ArchivedChangeIVec,
+) -> sled::Result<ArchivedChangeIVec> {
+    let value = match blob_tree {
+        Some(blob_tree) => read_blob(blob_tree, stored.as_ref())?,
+        None => stored,
+    };
+    value.as_ref().verify_checksum()?;
+    Ok(value)
 }
 
 /// Inserts any previously unseen entries from `changes` into the backup tree (`txn`) and returns the [`EncodedChanges`] that
@@ -21,17 +40,14 @@ pub fn write_changes_to_working_tree<K>(
 where
     K: DbKey,
 {
-    let mut reverse_changes = Vec::with_capacity(changes.changes.len());
-    let remove_bytes = unsafe {
-        ArchivedIVec::new(IVec::from(
-            Change::serialize_remove::<12>().as_ref(),
-        ))
-    };
+    let mut reverse_changes = ReverseChanges::with_capacity(changes.changes.len());
     for (key_bytes, change) in changes.changes.into_iter() {
         let key = K::from_sled_key(&key_bytes);
 
         let old_value = match change.as_ref() {
-            ArchivedChange::Insert(_) => txn.insert(&key_bytes, change.take_bytes())?,
+            ArchivedChange::Insert(_) | ArchivedChange::CompressedInsert(_) => {
+                txn.insert(&key_bytes, change.take_bytes())?
+            }
             ArchivedChange::Remove => txn.remove(&key_bytes)?,
         };
 
@@ -40,15 +56,431 @@ where
             continue;
         }
 
-        if let Some(old_value) = old_value {
-            reverse_changes.push((key_bytes, unsafe {
-                ArchivedChangeIVec::new(old_value)
-            }));
-        } else {
-            reverse_changes.push((key_bytes, remove_bytes.clone()));
+        reverse_changes.push(key_bytes, old_value);
+    }
+    Ok(reverse_changes.finish())
+}
+
+/// Computes the [`EncodedChanges`] that reverse `incoming` when applied on top of `current`, a snapshot of the values of the
+/// changed keys. Keys missing from `current` are reversed with a [`Change::Remove`].
+///
+/// This is the same reverse that [`GridDb::write_working_version`](crate::GridDb::write_working_version) backs up, minus the
+/// filtering of keys that were already backed up.
+pub fn compute_reverse_changes<K>(
+    current: &BTreeMap<K, Change>,
+    incoming: &EncodedChanges,
+) -> EncodedChanges
+where
+    K: DbKey,
+{
+    let mut reverse_changes = ReverseChanges::with_capacity(incoming.changes.len());
+    for (key_bytes, _) in incoming.changes.iter() {
+        let old_value = current
+            .get(&K::from_sled_key(key_bytes))
+            .map(|old_value| IVec::from(old_value.serialize().as_ref()));
+        reverse_changes.push(key_bytes.clone(), old_value);
+    }
+    reverse_changes.finish()
+}
+
+/// Collects the changes that reverse a write, one key at a time. Shared by [`write_changes_to_working_tree`] and
+/// [`compute_reverse_changes`], so both reverse a write the same way.
+struct ReverseChanges {
+    changes: Vec<(IVec, ArchivedChangeIVec)>,
+    remove_bytes: ArchivedChangeIVec,
+}
+
+impl ReverseChanges {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            changes: Vec::with_capacity(capacity),
+            remove_bytes: unsafe {
+                ArchivedIVec::new(IVec::from(Change::serialize_remove::<12>().as_ref()))
+            },
+        }
+    }
+
+    /// Records that the key held the serialized `old_value` before the write, or nothing if it's `None`. The reverse change
+    /// restores the old value, or removes the key.
+    fn push(&mut self, key_bytes: IVec, old_value: Option<IVec>) {
+        let reverse = match old_value {
+            Some(old_value) => unsafe { ArchivedChangeIVec::new(old_value) },
+            None => self.remove_bytes.clone(),
+        };
+        self.changes.push((key_bytes, reverse));
+    }
+
+    fn finish(self) -> EncodedChanges {
+        EncodedChanges {
+            changes: self.changes,
+        }
+    }
+}
+
+/// Applies `changes` to the working tree without computing the reverse changes.
+pub fn write_changes_to_working_tree_without_backup(
+    txn: &TransactionalTree,
+    changes: EncodedChanges,
+) -> Result<(), UnabortableTransactionError> {
+    for (key_bytes, change) in changes.changes.into_iter() {
+        match change.as_ref() {
+            ArchivedChange::Insert(_) | ArchivedChange::CompressedInsert(_) => {
+                txn.insert(&key_bytes, change.take_bytes())?;
+            }
+            ArchivedChange::Remove => {
+                txn.remove(&key_bytes)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Yields the [`Change`]s written to the working tree inside of an extent, blocking until they arrive.
+///
+/// Created by [`GridDb::watch_extent`](crate::GridDb::watch_extent). Inserts are decompressed and checked against their
+/// checksums, and an insert that is corrupted or can't be decompressed is yielded as a [`sled::Error::Io`] of kind
+/// [`std::io::ErrorKind::InvalidData`]. Content-addressed maps yield blob references, which are only verified once the blob
+/// is read.
+pub struct ExtentWatcher<K: DbKey> {
+    subscriber: Subscriber,
+    level: Level,
+    extent: Extent<K::Coords>,
+}
+
+impl<K> ExtentWatcher<K>
+where
+    K: DbKey,
+{
+    pub(crate) fn new(working_tree: &Tree, level: Level, extent: Extent<K::Coords>) -> Self {
+        // Subscribe to the longest prefix shared by every key in the Morton range. This will include some keys outside of the
+        // extent, so we filter them as they arrive.
+        let range = K::extent_range(level, extent.clone());
+        let (min_key, max_key) = (range.start().as_sled_key(), range.end().as_sled_key());
+        let prefix: Vec<u8> = min_key
+            .as_ref()
+            .iter()
+            .zip(max_key.as_ref().iter())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| *a)
+            .collect();
+        Self {
+            subscriber: working_tree.watch_prefix(prefix),
+            level,
+            extent,
         }
     }
-    Ok(EncodedChanges {
-        changes: reverse_changes,
-    })
+}
+
+impl<K> Iterator for ExtentWatcher<K>
+where
+    K: DbKey,
+{
+    type Item = Result<(K, Change), sled::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for event in &mut self.subscriber {
+            let (key_bytes, change) = match event {
+                Event::Insert { key, value } => {
+                    (key, unsafe { ArchivedChangeIVec::new(value) }.deserialize())
+                }
+                Event::Remove { key } => (key, Change::Remove),
+            };
+            let key = K::from_sled_key(&key_bytes);
+            if key.level() == self.level && K::extent_contains(&self.extent, key.coords()) {
+                return Some(
+                    change
+                        .decompress()
+                        .map(|change| (key, change))
+                        .map_err(Into::into),
+                );
+            }
+        }
+        None
+    }
+}
+
+/// Groups the working tree entries by the tile that contains them at some coarser level.
+///
+/// Created by [`GridDb::iter_working_tiles`](crate::GridDb::iter_working_tiles).
+pub struct WorkingTiles<K> {
+    /// One scan per level up to the tile level. Within a level, the tiles of the keys are in sled key order, since the tile of
+    /// a key is a prefix of its Morton code.
+    levels: Vec<TileLevelScan<K>>,
+    tile_level: Level,
+}
+
+struct TileLevelScan<K> {
+    /// `None` once the scan is exhausted.
+    entries: Option<sled::Iter>,
+    /// The next entry of this level along with its tile.
+    head: Option<(K, K, ArchivedChangeIVec)>,
+}
+
+impl<K> WorkingTiles<K>
+where
+    K: DbKey,
+{
+    pub(crate) fn new(working_tree: &Tree, tile_level: Level) -> Self {
+        // Nodes above the tile level aren't contained by any tile.
+        let levels = (0..=tile_level)
+            .map(|level| TileLevelScan {
+                entries: Some(
+                    working_tree
+                        .range(K::min_key(level).as_sled_key()..=K::max_key(level).as_sled_key()),
+                ),
+                head: None,
+            })
+            .collect();
+        Self { levels, tile_level }
+    }
+}
+
+impl<K> TileLevelScan<K>
+where
+    K: DbKey,
+{
+    /// Reads the next entry into `head` if it's empty.
+    fn fill_head(&mut self, tile_level: Level) -> Result<(), sled::Error> {
+        if self.head.is_some() {
+            return Ok(());
+        }
+        if let Some(entries) = &mut self.entries {
+            match entries.next().transpose()? {
+                Some((key_bytes, value)) => {
+                    let key = K::from_sled_key(&key_bytes);
+                    let tile = key.ancestor(tile_level);
+                    self.head = Some((tile, key, unsafe { ArchivedChangeIVec::new(value) }));
+                }
+                None => self.entries = None,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K> Iterator for WorkingTiles<K>
+where
+    K: DbKey,
+{
+    type Item = Result<(K, Vec<(K, ArchivedChangeIVec)>), sled::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Every level is in tile order, so the smallest tile at the head of any level comes next, and all of its entries are
+        // at the heads of the levels.
+        let mut next_tile: Option<K> = None;
+        for level in self.levels.iter_mut() {
+            if let Err(e) = level.fill_head(self.tile_level) {
+                return Some(Err(e));
+            }
+            if let Some((tile, _, _)) = &level.head {
+                let is_smaller = match &next_tile {
+                    Some(next) => tile.as_sled_key().as_ref() < next.as_sled_key().as_ref(),
+                    None => true,
+                };
+                if is_smaller {
+                    next_tile = Some(tile.clone());
+                }
+            }
+        }
+        let tile = next_tile?;
+
+        let mut entries = Vec::new();
+        for level in self.levels.iter_mut() {
+            while matches!(&level.head, Some((head_tile, _, _)) if *head_tile == tile) {
+                let (_, key, value) = level.head.take().unwrap();
+                entries.push((key, value));
+                if let Err(e) = level.fill_head(self.tile_level) {
+                    return Some(Err(e));
+                }
+            }
+        }
+        Some(Ok((tile, entries)))
+    }
+}
+
+/// Reads the working version entries at one level inside of an extent in batches, in Morton order.
+///
+/// Created by [`GridDb::extent_cursor`](crate::GridDb::extent_cursor). Each batch is read with a fresh scan that starts right
+/// after the last key the previous batch looked at, so the cursor holds no sled iterator between batches and sees writes
+/// made in the meantime to the part of the range it hasn't reached yet.
+pub struct ExtentCursor<K: DbKey> {
+    working_tree: Tree,
+    /// Set when the working tree holds references into the blob tree instead of values.
+    blob_tree: Option<Tree>,
+    extent: Extent<K::Coords>,
+    /// Where the next scan starts.
+    start: Bound<IVec>,
+    end: IVec,
+    exhausted: bool,
+}
+
+impl<K> ExtentCursor<K>
+where
+    K: DbKey,
+{
+    pub(crate) fn new(
+        working_tree: &Tree,
+        blob_tree: Option<&Tree>,
+        level: Level,
+        extent: Extent<K::Coords>,
+    ) -> Self {
+        let range = K::extent_range(level, extent.clone());
+        Self {
+            working_tree: working_tree.clone(),
+            blob_tree: blob_tree.cloned(),
+            extent,
+            start: Bound::Included(IVec::from(range.start().as_sled_key().as_ref())),
+            end: IVec::from(range.end().as_sled_key().as_ref()),
+            exhausted: false,
+        }
+    }
+
+    /// Reads up to `max` more entries inside of the extent, with their (decompressed) values. Keys in the extent's Morton range
+    /// but outside of the extent are skipped without counting toward `max`, and so are removes.
+    ///
+    /// Returns fewer than `max` entries only once the cursor is exhausted; every call after that returns an empty batch.
+    pub fn next_batch(&mut self, max: usize) -> Result<Vec<(K, Change)>, sled::Error> {
+        let mut batch = Vec::new();
+        if self.exhausted || max == 0 {
+            return Ok(batch);
+        }
+        let mut last_scanned = None;
+        for iter_result in self
+            .working_tree
+            .range((self.start.clone(), Bound::Included(self.end.clone())))
+        {
+            let (key_bytes, value) = iter_result?;
+            let key = K::from_sled_key(&key_bytes);
+            last_scanned = Some(key_bytes);
+            if !K::extent_contains(&self.extent, key.coords()) {
+                continue;
+            }
+            let stored = unsafe { ArchivedChangeIVec::new(value) };
+            // Should the working tree ever hold a remove, there's no value to return.
+            if matches!(stored.as_ref(), ArchivedChange::Remove) {
+                continue;
+            }
+            let value = resolve_stored_value(self.blob_tree.as_ref(), stored)?;
+            batch.push((key, value.deserialize().decompress()?));
+            if batch.len() == max {
+                break;
+            }
+        }
+        // Only move past the scanned keys once the whole batch was read, so a failed batch can be retried.
+        if let Some(key_bytes) = last_scanned {
+            self.start = Bound::Excluded(key_bytes);
+        }
+        self.exhausted = batch.len() < max;
+        Ok(batch)
+    }
+
+    /// Whether every entry has been returned. This only becomes known after a batch comes back short, so a cursor whose last
+    /// batch happened to end on the last entry isn't exhausted until the following (empty) batch.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChangeEncoder, DbKey3i32};
+
+    use ilattice::glam::IVec3;
+
+    fn reverse_of(
+        current: &BTreeMap<DbKey3i32, Change>,
+        incoming: Vec<(DbKey3i32, Change)>,
+    ) -> Vec<(DbKey3i32, Change)> {
+        let mut encoder = ChangeEncoder::default();
+        for (key, change) in incoming {
+            encoder.add_change(key, change);
+        }
+        compute_reverse_changes(current, &encoder.encode())
+            .changes
+            .into_iter()
+            .map(|(key, change)| (DbKey3i32::from_sled_key(&key), change.deserialize()))
+            .collect()
+    }
+
+    #[test]
+    fn reverse_insert_over_absent() {
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        assert_eq!(
+            reverse_of(&BTreeMap::new(), vec![(key, Change::Insert(Box::new([1])))]),
+            vec![(key, Change::Remove)]
+        );
+    }
+
+    #[test]
+    fn reverse_insert_over_present() {
+        let key = DbKey3i32::new(0, IVec3::ZERO.into());
+        let current = BTreeMap::from([(key, Change::Insert(Box::new([0])))]);
+        assert_eq!(
+            reverse_of(&current, vec![(key, Change::Insert(Box::new([1])))]),
+            vec![(key, Change::Insert(Box::new([0])))]
+        );
+    }
+
+    #[test]
+    fn reverse_removes() {
+        let [present, absent] = [0, 1].map(|i| DbKey3i32::new(0, IVec3::new(i, 0, 0).into()));
+        let current = BTreeMap::from([(present, Change::Insert(Box::new([0])))]);
+        assert_eq!(
+            reverse_of(
+                &current,
+                vec![(present, Change::Remove), (absent, Change::Remove)]
+            ),
+            vec![
+                (present, Change::Insert(Box::new([0]))),
+                (absent, Change::Remove)
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_back_up_the_computed_reverse() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let tree = open_working_tree("mymap", &db).unwrap();
+        let [present, absent] = [0, 1].map(|i| DbKey3i32::new(0, IVec3::new(i, 0, 0).into()));
+        let current = BTreeMap::from([(present, Change::Insert(Box::new([0])))]);
+        tree.insert(
+            present.as_sled_key(),
+            current[&present].serialize().as_ref(),
+        )
+        .unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_change(present, Change::Remove);
+        encoder.add_change(absent, Change::Insert(Box::new([1])));
+        let changes = encoder.encode();
+        let backup_keys = BackupKeyCache {
+            keys: Default::default(),
+        };
+        let written: Result<_, sled::transaction::TransactionError<()>> = tree.transaction(|txn| {
+            Ok(write_changes_to_working_tree(
+                txn,
+                &backup_keys,
+                changes.clone(),
+            )?)
+        });
+        let decode = |reverse: EncodedChanges| -> Vec<_> {
+            reverse
+                .changes
+                .into_iter()
+                .map(|(key, change)| (key, change.deserialize()))
+                .collect()
+        };
+        assert_eq!(
+            decode(written.unwrap()),
+            decode(compute_reverse_changes(&current, &changes))
+        );
+    }
 }